@@ -0,0 +1,259 @@
+use crate::commands::ReloadFn;
+use crate::config::ApiConfig;
+use crate::server_cluster::{ServerStateKind, ServerStatus};
+use crate::ReplCommand;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+struct ApiState {
+    token: Option<String>,
+    repl_sender: UnboundedSender<ReplCommand>,
+    reload: ReloadFn,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Serialize)]
+struct AcceptedResponse {
+    status: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ConsoleRequest {
+    command: String,
+}
+
+#[derive(Serialize)]
+struct ConsoleResponse {
+    output: Vec<String>,
+}
+
+/// Starts the HTTP control API. Every endpoint sends the same [`ReplCommand`]s as the REPL.
+pub fn spawn_api_server(
+    api_config: &ApiConfig,
+    repl_sender: UnboundedSender<ReplCommand>,
+    reload: ReloadFn,
+) -> Result<(), Box<dyn Error>> {
+    // The API can stop everything and run console commands, so only trust other machines with a token
+    if api_config.token.is_none() && !api_config.bind.ip().is_loopback() {
+        return Err("a token is required when bind isn't a loopback address".into());
+    }
+
+    let state = Arc::new(ApiState {
+        token: api_config.token.clone(),
+        repl_sender,
+        reload,
+    });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, state.clone())
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&api_config.bind)?.serve(make_service);
+    info!("Listening for API requests on {}", api_config.bind);
+    tokio::spawn(async move {
+        if let Err(why) = server.await {
+            error!("API server stopped: {}", why);
+        }
+    });
+    Ok(())
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    state: Arc<ApiState>,
+) -> Result<Response<Body>, Infallible> {
+    if let Some(token) = &state.token {
+        let is_authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|request_token| constant_time_eq(request_token.as_bytes(), token.as_bytes()))
+            .unwrap_or(false);
+        if !is_authorized {
+            return Ok(error_response(
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid bearer token".to_string(),
+            ));
+        }
+    }
+
+    let path = request.uri().path().trim_matches('/').to_string();
+    let segments: Vec<_> = path.split('/').collect();
+    let method = request.method().clone();
+    let response = match (&method, segments.as_slice()) {
+        (&Method::GET, ["servers"]) => match get_status(&state).await {
+            Some(status) => json_response(StatusCode::OK, &status),
+            None => unavailable_response(),
+        },
+        (&Method::GET, ["servers", server_name]) => {
+            match get_server_status(&state, server_name).await {
+                Ok(server_status) => json_response(StatusCode::OK, &server_status),
+                Err(response) => response,
+            }
+        }
+        (&Method::POST, ["servers", server_name, "console"]) => {
+            match send_console_command(&state, server_name, request).await {
+                Ok(output) => json_response(StatusCode::OK, &ConsoleResponse { output }),
+                Err(response) => response,
+            }
+        }
+        (&Method::POST, ["servers", server_name, action]) => {
+            if let Err(response) = get_server_status(&state, server_name).await {
+                return Ok(response);
+            }
+
+            let server_names = vec![server_name.to_string()];
+            match *action {
+                "restart" => send_command(&state, ReplCommand::Restart(server_names)),
+                "stop" => send_command(&state, ReplCommand::Stop(server_names)),
+                "start" => send_command(&state, ReplCommand::Start(server_names)),
+                _ => not_found_response(),
+            }
+        }
+        (&Method::POST, ["reload"]) => match (state.reload)() {
+            Ok(command) => send_command(&state, command),
+            Err(why) => error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to read config file: {}", why),
+            ),
+        },
+        (&Method::POST, ["stopold"]) => send_command(&state, ReplCommand::StopOld),
+        (&Method::POST, ["stopwraith"]) => send_command(&state, ReplCommand::StopWraith),
+        _ => not_found_response(),
+    };
+    Ok(response)
+}
+
+async fn get_status(state: &ApiState) -> Option<Vec<ServerStatus>> {
+    let (status_sender, status_receiver) = oneshot::channel();
+    state
+        .repl_sender
+        .send(ReplCommand::GetStatus(status_sender))
+        .ok()?;
+    status_receiver.await.ok()
+}
+
+async fn get_server_status(
+    state: &ApiState,
+    server_name: &str,
+) -> Result<ServerStatus, Response<Body>> {
+    let status = get_status(state).await.ok_or_else(unavailable_response)?;
+    status
+        .into_iter()
+        .find(|server_status| server_status.id == server_name)
+        .ok_or_else(|| {
+            error_response(
+                StatusCode::NOT_FOUND,
+                format!("Unknown server {}", server_name),
+            )
+        })
+}
+
+async fn send_console_command(
+    state: &ApiState,
+    server_name: &str,
+    request: Request<Body>,
+) -> Result<Vec<String>, Response<Body>> {
+    let server_status = get_server_status(state, server_name).await?;
+    if !matches!(
+        server_status.state,
+        ServerStateKind::Starting | ServerStateKind::Running
+    ) {
+        return Err(error_response(
+            StatusCode::CONFLICT,
+            format!("Server {} is not running", server_name),
+        ));
+    }
+
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|why| error_response(StatusCode::BAD_REQUEST, why.to_string()))?;
+    let console_request: ConsoleRequest = serde_json::from_slice(&body).map_err(|why| {
+        error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid console request: {}", why),
+        )
+    })?;
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    state
+        .repl_sender
+        .send(ReplCommand::Console(
+            server_name.to_string(),
+            console_request.command,
+            reply_sender,
+        ))
+        .map_err(|_| unavailable_response())?;
+    reply_receiver
+        .await
+        .map_err(|_| unavailable_response())?
+        .map_err(|why| error_response(StatusCode::BAD_GATEWAY, why))
+}
+
+fn send_command(state: &ApiState, command: ReplCommand) -> Response<Body> {
+    match state.repl_sender.send(command) {
+        Ok(()) => json_response(
+            StatusCode::ACCEPTED,
+            &AcceptedResponse { status: "accepted" },
+        ),
+        Err(_) => unavailable_response(),
+    }
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    match serde_json::to_string(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(why) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(why.to_string()))
+            .unwrap(),
+    }
+}
+
+fn error_response(status: StatusCode, error: String) -> Response<Body> {
+    json_response(status, &ErrorResponse { error })
+}
+
+fn not_found_response() -> Response<Body> {
+    error_response(StatusCode::NOT_FOUND, "Not found".to_string())
+}
+
+fn unavailable_response() -> Response<Body> {
+    error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "R2Wraith is shutting down".to_string(),
+    )
+}
+
+/// Compares tokens without stopping at the first difference, so the time taken doesn't give away
+/// how much of the token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b)
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
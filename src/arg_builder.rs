@@ -3,7 +3,7 @@ use crate::config::{
     PrivateLobbyPlayerPermissions, Riff,
 };
 use linked_hash_map::LinkedHashMap;
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
 trait IntoVarValue {
     fn into_var_value(self) -> Option<String>;
@@ -63,8 +63,9 @@ impl IntoVarValue for String {
 #[derive(Debug, Clone)]
 pub struct ArgBuilder {
     kv_env_args: LinkedHashMap<String, String>,
-    flag_args: HashSet<String>,
+    flag_args: BTreeSet<String>,
     kv_args: LinkedHashMap<String, String>,
+    extra_args: Vec<String>,
     playlist_vars: LinkedHashMap<String, String>,
 }
 
@@ -72,8 +73,9 @@ impl ArgBuilder {
     pub fn new() -> Self {
         ArgBuilder {
             kv_env_args: LinkedHashMap::new(),
-            flag_args: HashSet::new(),
+            flag_args: BTreeSet::new(),
             kv_args: LinkedHashMap::new(),
+            extra_args: Vec::new(),
             playlist_vars: LinkedHashMap::new(),
         }
     }
@@ -119,6 +121,10 @@ impl ArgBuilder {
         self.set_kv_env("NS_PORT", game_port)
     }
 
+    pub fn set_auth_port(self, auth_port: Option<u16>) -> Self {
+        self.set_kv_env("NS_PORT_AUTH", auth_port)
+    }
+
     pub fn set_description(self, description: String) -> Self {
         self.set_kv_env("NS_SERVER_DESC", description)
     }
@@ -395,6 +401,11 @@ impl ArgBuilder {
         self
     }
 
+    pub fn add_extra_args(mut self, extra_args: Vec<String>) -> Self {
+        self.extra_args.extend(extra_args);
+        self
+    }
+
     pub fn set_game_config(self, game_config: FilledGameConfig) -> Self {
         self.set_description(game_config.description)
             .set_password(game_config.password)
@@ -419,9 +430,10 @@ impl ArgBuilder {
             .set_playlist_overrides(game_config.playlist_overrides)
             .add_extra_playlist_vars(game_config.extra_playlist_vars)
             .add_extra_vars(game_config.extra_vars)
+            .add_extra_args(game_config.extra_args)
     }
 
-    pub fn build(self, out_envs: &mut Vec<String>) {
+    pub fn finish(self) -> BuiltArgs {
         let mut extra_args = Vec::new();
         extra_args.extend(self.flag_args);
         extra_args.extend(
@@ -429,20 +441,47 @@ impl ArgBuilder {
                 .into_iter()
                 .flat_map(|(key, value)| [key, value]),
         );
+        // Extra args come after everything we set, so they can override it
+        extra_args.extend(self.extra_args);
+
+        BuiltArgs {
+            env_vars: self.kv_env_args,
+            extra_args,
+            playlist_vars: self.playlist_vars,
+        }
+    }
+}
+
+/// A server's arguments, before they're combined into environment variables.
+#[derive(Debug, Clone)]
+pub struct BuiltArgs {
+    pub env_vars: LinkedHashMap<String, String>,
+    pub extra_args: Vec<String>,
+    pub playlist_vars: LinkedHashMap<String, String>,
+}
+
+impl BuiltArgs {
+    /// Every argument that goes into `NS_EXTRA_ARGUMENTS`, including the playlist overrides.
+    pub fn extra_arg_tokens(&self) -> Vec<String> {
+        let mut extra_args = self.extra_args.clone();
         extra_args.push("+setplaylistvaroverrides".to_string());
         let playlist_args_list: Vec<_> = self
             .playlist_vars
-            .into_iter()
-            .flat_map(|(key, value)| [key, value])
+            .iter()
+            .flat_map(|(key, value)| [key.as_str(), value.as_str()])
             .collect();
         extra_args.push(playlist_args_list.join(" "));
+        extra_args
+    }
 
-        let mut env_args = self.kv_env_args;
+    pub fn build(self, out_envs: &mut Vec<String>) {
+        let extra_args = self.extra_arg_tokens();
+        let mut env_args = self.env_vars;
         env_args.insert(
             "NS_EXTRA_ARGUMENTS".to_string(),
             extra_args
                 .iter()
-                .map(|arg| format!("\"{}\"", arg))
+                .map(|arg| quote_arg(arg))
                 .collect::<Vec<_>>()
                 .join(" "),
         );
@@ -453,3 +492,75 @@ impl ArgBuilder {
         );
     }
 }
+
+/// Quotes an argument following the Windows command line rules, since the dedicated server runs
+/// under Wine. Backslashes are only special when they come before a quote.
+fn quote_arg(arg: &str) -> String {
+    let mut quoted_arg = String::from("\"");
+    let mut backslash_count = 0;
+    for char in arg.chars() {
+        match char {
+            '\\' => backslash_count += 1,
+            '"' => {
+                quoted_arg.extend(std::iter::repeat_n('\\', backslash_count * 2 + 1));
+                quoted_arg.push('"');
+                backslash_count = 0;
+            }
+            _ => {
+                quoted_arg.extend(std::iter::repeat_n('\\', backslash_count));
+                quoted_arg.push(char);
+                backslash_count = 0;
+            }
+        }
+    }
+    quoted_arg.extend(std::iter::repeat_n('\\', backslash_count * 2));
+    quoted_arg.push('"');
+    quoted_arg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_arg_follows_windows_rules() {
+        let cases = [
+            ("", r#""""#),
+            ("+mp_gamemode", r#""+mp_gamemode""#),
+            ("Alpha Server", r#""Alpha Server""#),
+            (r#"say "hi""#, r#""say \"hi\"""#),
+            (r#"a\"b"#, r#""a\\\"b""#),
+            (r#"a\\"b"#, r#""a\\\\\"b""#),
+            (r"C:\games\mods", r#""C:\games\mods""#),
+            (r"C:\games\", r#""C:\games\\""#),
+            (r"trailing\\", r#""trailing\\\\""#),
+        ];
+        for (arg, quoted_arg) in cases {
+            assert_eq!(quote_arg(arg), quoted_arg, "{}", arg);
+        }
+    }
+
+    #[test]
+    fn build_quotes_extra_arguments() {
+        let mut env_vars = LinkedHashMap::new();
+        env_vars.insert("NS_SERVER_NAME".to_string(), "Alpha".to_string());
+        let mut playlist_vars = LinkedHashMap::new();
+        playlist_vars.insert("max_players".to_string(), "16".to_string());
+        playlist_vars.insert("scorelimit".to_string(), "50".to_string());
+        let built_args = BuiltArgs {
+            env_vars,
+            extra_args: vec!["+mp_gamemode".to_string(), "say \"hi\"".to_string()],
+            playlist_vars,
+        };
+
+        let mut out_envs = Vec::new();
+        built_args.build(&mut out_envs);
+        assert_eq!(
+            out_envs,
+            vec![
+                "NS_SERVER_NAME=Alpha".to_string(),
+                r#"NS_EXTRA_ARGUMENTS="+mp_gamemode" "say \"hi\"" "+setplaylistvaroverrides" "max_players 16 scorelimit 50""#.to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,761 @@
+use crate::config::{Config, FilledInstanceConfig, HostConfig, ReadyCheck, RestartPolicy};
+use crate::runtime;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Modes that come with the game or Northstar. Mods can add more, so others are only warned about.
+const KNOWN_MODES: &[&str] = &[
+    "aitdm",
+    "at",
+    "chamber",
+    "coliseum",
+    "cp",
+    "ctf",
+    "ctf_comp",
+    "fastball",
+    "fd",
+    "ffa",
+    "fra",
+    "fw",
+    "gg",
+    "hidden",
+    "hs",
+    "inf",
+    "kr",
+    "lts",
+    "mfd",
+    "ps",
+    "sns",
+    "speedball",
+    "tdm",
+    "tffa",
+    "tt",
+    "ttdm",
+];
+
+/// Maps that come with the game. Mods can add more, so others are only warned about.
+const KNOWN_MAPS: &[&str] = &[
+    "mp_angel_city",
+    "mp_black_water_canal",
+    "mp_coliseum",
+    "mp_coliseum_column",
+    "mp_colony02",
+    "mp_complex3",
+    "mp_crashsite3",
+    "mp_drydock",
+    "mp_eden",
+    "mp_forwardbase_kodai",
+    "mp_glitch",
+    "mp_grave",
+    "mp_homestead",
+    "mp_lf_deck",
+    "mp_lf_meadow",
+    "mp_lf_stacks",
+    "mp_lf_township",
+    "mp_lf_traffic",
+    "mp_lf_uma",
+    "mp_relic02",
+    "mp_rise",
+    "mp_thaw",
+    "mp_wargames",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+struct Problem {
+    severity: Severity,
+    line: Option<usize>,
+    message: String,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+pub fn format_key_path(table: &str, key: &str) -> String {
+    if table.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", table, key)
+    }
+}
+
+/// Validates a config file without starting any servers, printing any problems that are found.
+/// Returns a non-zero exit code if there are any errors.
+pub fn run_check(args: Vec<String>) -> i32 {
+    let config_file_path = args.first().map(String::as_str).unwrap_or("config.toml");
+    let full_config_path = std::env::current_dir().unwrap().join(config_file_path);
+
+    let config_text = match std::fs::read_to_string(&full_config_path) {
+        Ok(config_text) => config_text,
+        Err(why) => {
+            eprintln!("{}: error: {}", config_file_path, why);
+            return 1;
+        }
+    };
+    let config: Config = match toml::from_str(&config_text) {
+        Ok(config) => config,
+        Err(why) => {
+            eprintln!("{}: error: {}", config_file_path, why);
+            return 1;
+        }
+    };
+
+    let config_dir = full_config_path.parent().unwrap();
+    let mut checker = ConfigChecker {
+        config_lines: ConfigLines::new(&config_text),
+        problems: Vec::new(),
+    };
+    checker.check(&config, config_dir);
+
+    let mut error_count = 0;
+    let mut warning_count = 0;
+    for problem in &checker.problems {
+        match problem.severity {
+            Severity::Error => error_count += 1,
+            Severity::Warning => warning_count += 1,
+        }
+        match problem.line {
+            Some(line) => eprintln!(
+                "{}:{}: {}: {}",
+                config_file_path, line, problem.severity, problem.message
+            ),
+            None => eprintln!(
+                "{}: {}: {}",
+                config_file_path, problem.severity, problem.message
+            ),
+        }
+    }
+
+    if error_count > 0 {
+        eprintln!(
+            "{} has {} error(s) and {} warning(s)",
+            config_file_path, error_count, warning_count
+        );
+        1
+    } else {
+        println!(
+            "{} is valid, with {} warning(s)",
+            config_file_path, warning_count
+        );
+        0
+    }
+}
+
+struct ConfigChecker {
+    config_lines: ConfigLines,
+    problems: Vec<Problem>,
+}
+
+impl ConfigChecker {
+    fn report(&mut self, severity: Severity, line: Option<usize>, message: String) {
+        // Problems with the defaults would otherwise be reported once for every server
+        let is_duplicate = self.problems.iter().any(|problem| {
+            problem.severity == severity && problem.line == line && problem.message == message
+        });
+        if is_duplicate {
+            return;
+        }
+
+        self.problems.push(Problem {
+            severity,
+            line,
+            message,
+        });
+    }
+
+    fn check(&mut self, config: &Config, config_dir: &Path) {
+        for (table, key) in config.unknown_keys() {
+            let line = self.config_lines.find_key(&table, &key);
+            self.report(
+                Severity::Error,
+                line,
+                format!("unknown key {}", format_key_path(&table, &key)),
+            );
+        }
+
+        self.check_hosts(config, config_dir);
+
+        if let Some(api_config) = &config.api {
+            if api_config.token.is_none() && !api_config.bind.ip().is_loopback() {
+                let line = self.config_lines.find_key("api", "bind");
+                self.report(
+                    Severity::Error,
+                    line,
+                    "api.token is required when api.bind isn't a loopback address".to_string(),
+                );
+            }
+        }
+
+        if config.rollout.concurrency == 0 {
+            let line = self.config_lines.find_key("rollout", "concurrency");
+            self.report(
+                Severity::Error,
+                line,
+                "rollout.concurrency must be at least 1".to_string(),
+            );
+        }
+        let ready_timeout_seconds = config.rollout.ready_timeout_seconds;
+        if ready_timeout_seconds.is_nan() || ready_timeout_seconds <= 0. {
+            let line = self
+                .config_lines
+                .find_key("rollout", "ready-timeout-seconds");
+            self.report(
+                Severity::Error,
+                line,
+                "rollout.ready-timeout-seconds must be more than 0".to_string(),
+            );
+        }
+
+        let servers: Vec<_> = config
+            .servers
+            .iter()
+            .map(|(id, instance_config)| {
+                let filled_instance_config =
+                    instance_config
+                        .clone()
+                        .make_filled(id, config.defaults.clone(), config_dir);
+                (id.as_str(), filled_instance_config)
+            })
+            .collect();
+
+        if config.hosts.is_empty() {
+            self.check_ports(
+                "game-ports",
+                self.config_lines.find_key("", "game-ports"),
+                "game-port",
+                Some(&config.game_ports),
+                servers
+                    .iter()
+                    .map(|(id, instance_config)| (*id, instance_config.game_port)),
+            );
+            self.check_ports(
+                "auth-ports",
+                self.config_lines.find_key("", "auth-ports"),
+                "auth-port",
+                config.auth_ports.as_ref(),
+                servers
+                    .iter()
+                    .map(|(id, instance_config)| (*id, instance_config.auth_port)),
+            );
+        }
+
+        // Servers that aren't pinned could go on any host, so only pinned ones can be checked
+        // against a host's ports
+        let host_configs = config.host_configs();
+        for (id, instance_config) in &servers {
+            if let Some(host_name) = &instance_config.host {
+                if !host_configs
+                    .iter()
+                    .any(|host_config| &host_config.name == host_name)
+                {
+                    let line = self
+                        .config_lines
+                        .find_key(&format!("servers.{}", id), "host");
+                    self.report(
+                        Severity::Error,
+                        line,
+                        format!("server {} is pinned to unknown host {}", id, host_name),
+                    );
+                }
+            }
+        }
+        for (host_index, host_config) in config.hosts.iter().enumerate() {
+            let pinned_servers: Vec<_> = servers
+                .iter()
+                .filter(|(_, instance_config)| {
+                    instance_config.host.as_ref() == Some(&host_config.name)
+                })
+                .collect();
+            let ranges: [(_, _, _, _, fn(&FilledInstanceConfig) -> _); 2] = [
+                (
+                    "game-ports",
+                    "game-port",
+                    &host_config.game_ports,
+                    Some(&config.game_ports),
+                    |instance_config| instance_config.game_port,
+                ),
+                (
+                    "auth-ports",
+                    "auth-port",
+                    &host_config.auth_ports,
+                    config.auth_ports.as_ref(),
+                    |instance_config| instance_config.auth_port,
+                ),
+            ];
+            for (range_key, port_key, host_range, top_level_range, server_port) in ranges {
+                let (range_key_path, range_line) = match host_range {
+                    Some(_) => (
+                        format!("hosts.{}.{}", host_config.name, range_key),
+                        self.config_lines
+                            .find_array_key("hosts", host_index, range_key),
+                    ),
+                    None => (
+                        range_key.to_string(),
+                        self.config_lines.find_key("", range_key),
+                    ),
+                };
+                self.check_ports(
+                    &range_key_path,
+                    range_line,
+                    port_key,
+                    host_range.as_ref().or(top_level_range),
+                    pinned_servers
+                        .iter()
+                        .map(|(id, instance_config)| (*id, server_port(instance_config))),
+                );
+            }
+
+            if let Some(capacity) = host_config.capacity {
+                if pinned_servers.len() > capacity {
+                    let line = self
+                        .config_lines
+                        .find_array_key("hosts", host_index, "capacity");
+                    self.report(
+                        Severity::Warning,
+                        line,
+                        format!(
+                            "host {} has a capacity of {} but {} server(s) are pinned to it, so they can't all run at once",
+                            host_config.name,
+                            capacity,
+                            pinned_servers.len()
+                        ),
+                    );
+                }
+            }
+        }
+
+        for (id, instance_config) in &servers {
+            self.check_server(id, instance_config);
+        }
+    }
+
+    fn check_hosts(&mut self, config: &Config, config_dir: &Path) {
+        if config.hosts.is_empty() {
+            self.check_host_connection(&config.host_configs()[0], None, config_dir);
+            return;
+        }
+
+        for table in ["runtime", "docker"] {
+            if let Some(line) = self.config_lines.find_table(table) {
+                self.report(
+                    Severity::Warning,
+                    Some(line),
+                    format!(
+                        "[{}] is ignored when there are [[hosts]], set its options on each host instead",
+                        table
+                    ),
+                );
+            }
+        }
+
+        let mut host_names = HashSet::new();
+        for (host_index, host_config) in config.hosts.iter().enumerate() {
+            let line = self
+                .config_lines
+                .find_array_key("hosts", host_index, "name");
+            if host_config.name.is_empty() {
+                self.report(
+                    Severity::Error,
+                    line,
+                    "hosts can't have an empty name".to_string(),
+                );
+            } else if !host_names.insert(&host_config.name) {
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!("there's more than one host called {}", host_config.name),
+                );
+            }
+            if host_config.capacity == Some(0) {
+                let line = self
+                    .config_lines
+                    .find_array_key("hosts", host_index, "capacity");
+                self.report(
+                    Severity::Warning,
+                    line,
+                    format!(
+                        "host {} has a capacity of 0, so no servers will run on it",
+                        host_config.name
+                    ),
+                );
+            }
+
+            self.check_host_connection(host_config, Some(host_index), config_dir);
+        }
+    }
+
+    /// Checks how the host's runtime is connected to. The implicit host without any `[[hosts]]`
+    /// has no index, and its options are in `[runtime]` and `[docker]`.
+    fn check_host_connection(
+        &mut self,
+        host_config: &HostConfig,
+        host_index: Option<usize>,
+        config_dir: &Path,
+    ) {
+        let key_path = |key: &str| match host_index {
+            Some(_) => format!("hosts.{}.{}", host_config.name, key),
+            None if key == "socket" => format!("runtime.{}", key),
+            None => format!("docker.{}", key),
+        };
+
+        if let Some(host) = &host_config.docker.host {
+            let line = self.find_host_key(host_index, "host");
+            if host_config.runtime.socket.is_some() {
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!(
+                        "only one of {} and {} can be set",
+                        key_path("host"),
+                        key_path("socket")
+                    ),
+                );
+            }
+            if let Err(why) = runtime::parse_docker_host(host) {
+                self.report(Severity::Error, line, why);
+            }
+        }
+
+        if let Err(why) = runtime::find_tls_files(&host_config.docker, config_dir) {
+            let line = match host_index {
+                Some(host_index) => self.config_lines.find_array_table("hosts", host_index),
+                None => self.config_lines.find_table("docker"),
+            };
+            self.report(Severity::Error, line, why);
+        }
+
+        for (key, seconds) in [
+            (
+                "connect-timeout-seconds",
+                host_config.docker.connect_timeout_seconds,
+            ),
+            (
+                "request-timeout-seconds",
+                host_config.docker.request_timeout_seconds,
+            ),
+        ] {
+            if seconds.is_nan() || seconds <= 0. {
+                let line = self.find_host_key(host_index, key);
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!("{} must be more than 0", key_path(key)),
+                );
+            }
+        }
+    }
+
+    fn find_host_key(&self, host_index: Option<usize>, key: &str) -> Option<usize> {
+        match host_index {
+            Some(host_index) => self.config_lines.find_array_key("hosts", host_index, key),
+            None if key == "socket" => self.config_lines.find_key("runtime", key),
+            None => self.config_lines.find_key("docker", key),
+        }
+    }
+
+    fn check_ports<'a>(
+        &mut self,
+        range_key: &str,
+        range_line: Option<usize>,
+        port_key: &str,
+        range: Option<&RangeInclusive<u16>>,
+        server_ports: impl Iterator<Item = (&'a str, Option<u16>)>,
+    ) {
+        if let Some(range) = range {
+            let line = range_line;
+            if range.is_empty() {
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!(
+                        "{} start {} is after the end {}",
+                        range_key,
+                        range.start(),
+                        range.end()
+                    ),
+                );
+            } else if *range.start() == 0 {
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!("{} can't include port 0", range_key),
+                );
+            }
+        }
+
+        let mut port_servers: HashMap<u16, &str> = HashMap::new();
+        let mut allocated_count = 0;
+        for (id, port) in server_ports {
+            let port = match port {
+                Some(port) => port,
+                None => {
+                    allocated_count += 1;
+                    continue;
+                }
+            };
+            let line = self
+                .config_lines
+                .find_key(&format!("servers.{}", id), port_key);
+            if port == 0 {
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!("server {} can't use {} 0", id, port_key),
+                );
+            }
+            if let Some(other_id) = port_servers.insert(port, id) {
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!(
+                        "servers {} and {} both use {} {}",
+                        other_id, id, port_key, port
+                    ),
+                );
+            }
+            if range.map(|range| range.contains(&port)).unwrap_or(false) {
+                self.report(
+                    Severity::Warning,
+                    line,
+                    format!(
+                        "server {} {} {} is inside {}, so another server might take it first",
+                        id, port_key, port, range_key
+                    ),
+                );
+            }
+        }
+
+        if let Some(range) = range {
+            let free_count = range
+                .clone()
+                .filter(|port| !port_servers.contains_key(port))
+                .count();
+            if allocated_count > free_count {
+                let line = range_line;
+                self.report(
+                    Severity::Warning,
+                    line,
+                    format!(
+                        "{} only has {} free port(s) for {} server(s), so they can't all run at once",
+                        range_key, free_count, allocated_count
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_server(&mut self, id: &str, instance_config: &FilledInstanceConfig) {
+        let table = format!("servers.{}", id);
+        let game_config = &instance_config.game_config;
+
+        if game_config.docker_image.is_empty() {
+            let line = self.config_lines.find_table(&table);
+            self.report(
+                Severity::Error,
+                line,
+                format!("server {} doesn't have a docker-image", id),
+            );
+        }
+
+        if !Path::new(&game_config.game_dir).is_dir() {
+            let (source, line) = self.find_game_config_key(id, "game-dir");
+            self.report(
+                Severity::Error,
+                line,
+                format!("{} game-dir {} doesn't exist", source, game_config.game_dir),
+            );
+        }
+
+        let mut mods: Vec<_> = game_config.mods.iter().collect();
+        mods.sort();
+        for mod_dir in mods {
+            if !Path::new(mod_dir).is_dir() {
+                let (source, line) = self.find_game_config_key(id, "mods");
+                self.report(
+                    Severity::Error,
+                    line,
+                    format!("{} mod directory {} doesn't exist", source, mod_dir),
+                );
+            }
+        }
+
+        for (key, mode) in [
+            ("mode", &game_config.mode),
+            ("default-mode", &game_config.default_mode),
+        ] {
+            if let Some(mode) = mode {
+                if !KNOWN_MODES.contains(&mode.as_str()) {
+                    let (source, line) = self.find_game_config_key(id, key);
+                    self.report(
+                        Severity::Warning,
+                        line,
+                        format!(
+                            "{} {} {} isn't a known mode, ignore this if it's added by a mod",
+                            source, key, mode
+                        ),
+                    );
+                }
+            }
+        }
+
+        for (key, map) in [
+            ("map", &game_config.map),
+            ("default-map", &game_config.default_map),
+        ] {
+            if let Some(map) = map {
+                if !KNOWN_MAPS.contains(&map.as_str()) {
+                    let (source, line) = self.find_game_config_key(id, key);
+                    self.report(
+                        Severity::Warning,
+                        line,
+                        format!(
+                            "{} {} {} isn't a known map, ignore this if it's added by a mod",
+                            source, key, map
+                        ),
+                    );
+                }
+            }
+        }
+
+        if game_config.ready_check == ReadyCheck::Log && game_config.ready_log_pattern.is_none() {
+            let (source, line) = self.find_game_config_key(id, "ready-check");
+            self.report(
+                Severity::Error,
+                line,
+                format!(
+                    "{} ready-check is \"log\" but there's no ready-log-pattern to wait for",
+                    source
+                ),
+            );
+        }
+
+        if game_config.restart_policy == RestartPolicy::AfterMatch
+            && (game_config.match_start_log_pattern.is_none()
+                || game_config.match_end_log_pattern.is_none())
+        {
+            let (source, line) = self.find_game_config_key(id, "restart-policy");
+            self.report(
+                Severity::Error,
+                line,
+                format!(
+                    "{} restart-policy is \"after-match\" but matches can't be tracked without a match-start-log-pattern and match-end-log-pattern",
+                    source
+                ),
+            );
+        }
+
+        let start_timeout_seconds = game_config.start_timeout_seconds;
+        if start_timeout_seconds.is_nan() || start_timeout_seconds <= 0. {
+            let (source, line) = self.find_game_config_key(id, "start-timeout-seconds");
+            self.report(
+                Severity::Error,
+                line,
+                format!("{} start-timeout-seconds must be more than 0", source),
+            );
+        }
+    }
+
+    /// Finds where a server's game config option was set, which might be in the defaults.
+    fn find_game_config_key(&self, id: &str, key: &str) -> (String, Option<usize>) {
+        let table = format!("servers.{}", id);
+        if let Some(line) = self.config_lines.find_key(&table, key) {
+            return (format!("server {}", id), Some(line));
+        }
+        match self.config_lines.find_key("defaults", key) {
+            Some(line) => ("defaults".to_string(), Some(line)),
+            None => (
+                format!("server {}", id),
+                self.config_lines.find_table(&table),
+            ),
+        }
+    }
+}
+
+/// A rough index of which line each key is on, since the parsed config doesn't keep track. Only
+/// keys written as `key = value` under a `[table]` header are found.
+struct ConfigLines {
+    keys: Vec<(String, String, usize)>,
+    tables: Vec<(String, usize)>,
+}
+
+impl ConfigLines {
+    fn new(config_text: &str) -> Self {
+        let mut keys = Vec::new();
+        let mut tables = Vec::new();
+        let mut current_table = String::new();
+        for (line_index, line) in config_text.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.trim();
+            if line.starts_with('[') {
+                current_table = line
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or("")
+                    .split('.')
+                    .map(|part| part.trim().trim_matches('"'))
+                    .collect::<Vec<_>>()
+                    .join(".");
+                tables.push((current_table.clone(), line_number));
+            } else if let Some((key, _)) = line.split_once('=') {
+                let key = key.trim().trim_matches('"').to_string();
+                keys.push((current_table.clone(), key, line_number));
+            }
+        }
+        ConfigLines { keys, tables }
+    }
+
+    fn find_key(&self, table: &str, key: &str) -> Option<usize> {
+        self.keys
+            .iter()
+            .find(|(key_table, line_key, _)| key_table == table && line_key == key)
+            .map(|(_, _, line_number)| *line_number)
+    }
+
+    fn find_table(&self, table: &str) -> Option<usize> {
+        self.tables
+            .iter()
+            .find(|(line_table, _)| line_table == table)
+            .map(|(_, line_number)| *line_number)
+    }
+
+    /// Finds the header of an entry in an array of tables, like the second `[[hosts]]`.
+    fn find_array_table(&self, table: &str, index: usize) -> Option<usize> {
+        self.tables
+            .iter()
+            .filter(|(line_table, _)| line_table == table)
+            .nth(index)
+            .map(|(_, line_number)| *line_number)
+    }
+
+    fn find_array_key(&self, table: &str, index: usize, key: &str) -> Option<usize> {
+        let start_line = self.find_array_table(table, index)?;
+        let end_line = self
+            .tables
+            .iter()
+            .map(|(_, line_number)| *line_number)
+            .find(|line_number| *line_number > start_line)
+            .unwrap_or(usize::MAX);
+        self.keys
+            .iter()
+            .find(|(key_table, line_key, line_number)| {
+                key_table == table
+                    && line_key == key
+                    && *line_number > start_line
+                    && *line_number < end_line
+            })
+            .map(|(_, _, line_number)| *line_number)
+    }
+}
@@ -0,0 +1,300 @@
+use crate::server_cluster::ServerStatus;
+use crate::ReplCommand;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+pub type ReloadFn = Arc<dyn Fn() -> Result<ReplCommand, Box<dyn Error>> + Send + Sync>;
+
+#[derive(Debug, Default)]
+pub struct CommandOutput {
+    pub lines: Vec<String>,
+    pub is_error: bool,
+    pub is_stopping: bool,
+}
+
+impl CommandOutput {
+    fn line(line: String) -> Self {
+        CommandOutput {
+            lines: vec![line],
+            ..Default::default()
+        }
+    }
+
+    fn error(line: String) -> Self {
+        CommandOutput {
+            lines: vec![line],
+            is_error: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Runs the text commands shared by the REPL and the control socket.
+#[derive(Clone)]
+pub struct CommandRunner {
+    repl_sender: UnboundedSender<ReplCommand>,
+    reload: ReloadFn,
+}
+
+impl CommandRunner {
+    pub fn new(repl_sender: UnboundedSender<ReplCommand>, reload: ReloadFn) -> Self {
+        CommandRunner {
+            repl_sender,
+            reload,
+        }
+    }
+
+    pub async fn run(&self, command: &str) -> CommandOutput {
+        let command = command.trim();
+
+        if command == "help" || command == "?" {
+            CommandOutput {
+                lines: vec![
+                    "Available commands:".to_string(),
+                    "  version - Display the version of R2Wraith".to_string(),
+                    "  status or list - Display the state of every server, add --json for JSON output".to_string(),
+                    "  stopwraith - Stop R2Wraith, keeping servers running and writing a restore file".to_string(),
+                    "  stopall - Shutdown all servers and stop R2Wraith".to_string(),
+                    "  restartall - Restart all servers".to_string(),
+                    "  restart [name] - Restart a server by name".to_string(),
+                    "  rollout [--concurrency N] [names...] - Restart servers a few at a time, waiting for each to be ready".to_string(),
+                    "  stop [name] - Stop a server by name, keeping it stopped until it's started again".to_string(),
+                    "  start [name] - Start a server that was stopped or is crash-looping".to_string(),
+                    "  console [name] [command] - Send a command to a server's console and display its reply".to_string(),
+                    "  reload - Reload the configuration file, starting any added servers".to_string(),
+                    "  stopold - Stop any servers that have been removed from configuration".to_string(),
+                ],
+                ..Default::default()
+            }
+        } else if command == "version" {
+            CommandOutput::line(format!("R2Wraith {}", env!("CARGO_PKG_VERSION")))
+        } else if command == "status" || command == "list" {
+            self.status(false).await
+        } else if command == "status --json" || command == "list --json" {
+            self.status(true).await
+        } else if command == "stopwraith" {
+            let mut output = self.send(ReplCommand::StopWraith);
+            output.is_stopping = true;
+            output
+        } else if command == "stopall" {
+            let mut output = self.send(ReplCommand::StopAll);
+            output.is_stopping = true;
+            output
+        } else if command == "restartall" {
+            self.send(ReplCommand::RestartAll)
+        } else if command == "reload" {
+            match (self.reload)() {
+                Ok(reload_command) => self.send(reload_command),
+                Err(why) => CommandOutput::error(format!("Failed to read config file: {}", why)),
+            }
+        } else if command == "stopold" {
+            self.send(ReplCommand::StopOld)
+        } else if command == "rollout" || command.starts_with("rollout ") {
+            self.rollout(&command["rollout".len()..]).await
+        } else if let Some(server_names) = command.strip_prefix("restart ") {
+            self.send(ReplCommand::Restart(split_server_names(server_names)))
+        } else if let Some(server_names) = command.strip_prefix("stop ") {
+            self.send(ReplCommand::Stop(split_server_names(server_names)))
+        } else if let Some(server_names) = command.strip_prefix("start ") {
+            self.send(ReplCommand::Start(split_server_names(server_names)))
+        } else if let Some(console_args) = command.strip_prefix("console ") {
+            match console_args.trim().split_once(char::is_whitespace) {
+                Some((server_name, console_command)) => {
+                    self.console(server_name, console_command.trim()).await
+                }
+                None => CommandOutput::error("Usage: console [name] [command]".to_string()),
+            }
+        } else {
+            CommandOutput::error(format!("Unknown command: {}", command))
+        }
+    }
+
+    async fn status(&self, is_json: bool) -> CommandOutput {
+        let (status_sender, status_receiver) = oneshot::channel();
+        let status = match self.repl_sender.send(ReplCommand::GetStatus(status_sender)) {
+            Ok(()) => status_receiver.await.ok(),
+            Err(_) => None,
+        };
+        let status = match status {
+            Some(status) => status,
+            None => return CommandOutput::error("R2Wraith is shutting down".to_string()),
+        };
+
+        if is_json {
+            match serde_json::to_string(&status) {
+                Ok(json) => CommandOutput::line(json),
+                Err(why) => CommandOutput::error(format!("Failed to serialize status: {}", why)),
+            }
+        } else {
+            CommandOutput {
+                lines: format_status_table(&status),
+                ..Default::default()
+            }
+        }
+    }
+
+    async fn console(&self, server_name: &str, console_command: &str) -> CommandOutput {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let command = ReplCommand::Console(
+            server_name.to_string(),
+            console_command.to_string(),
+            reply_sender,
+        );
+        if self.repl_sender.send(command).is_err() {
+            return CommandOutput::error("R2Wraith is shutting down".to_string());
+        }
+
+        match reply_receiver.await {
+            Ok(Ok(lines)) => CommandOutput {
+                lines,
+                ..Default::default()
+            },
+            Ok(Err(why)) => CommandOutput::error(why),
+            Err(_) => CommandOutput::error("R2Wraith is shutting down".to_string()),
+        }
+    }
+
+    async fn rollout(&self, rollout_args: &str) -> CommandOutput {
+        let mut server_names = Vec::new();
+        let mut concurrency = None;
+        let mut rollout_args = rollout_args.split_whitespace();
+        while let Some(rollout_arg) = rollout_args.next() {
+            if rollout_arg == "--concurrency" {
+                match rollout_args.next().and_then(|value| value.parse().ok()) {
+                    Some(value) => concurrency = Some(value),
+                    None => {
+                        return CommandOutput::error(
+                            "Usage: rollout [--concurrency N] [names...]".to_string(),
+                        )
+                    }
+                }
+            } else {
+                server_names.push(rollout_arg.to_string());
+            }
+        }
+
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let command = ReplCommand::Rollout(server_names, concurrency, reply_sender);
+        if self.repl_sender.send(command).is_err() {
+            return CommandOutput::error("R2Wraith is shutting down".to_string());
+        }
+        match reply_receiver.await {
+            Ok(Ok(())) => CommandOutput::line("Rollout started".to_string()),
+            Ok(Err(why)) => CommandOutput::error(why),
+            Err(_) => CommandOutput::error("R2Wraith is shutting down".to_string()),
+        }
+    }
+
+    fn send(&self, command: ReplCommand) -> CommandOutput {
+        match self.repl_sender.send(command) {
+            Ok(()) => CommandOutput::default(),
+            Err(_) => CommandOutput::error("R2Wraith is shutting down".to_string()),
+        }
+    }
+}
+
+fn split_server_names(server_names: &str) -> Vec<String> {
+    server_names
+        .split_whitespace()
+        .map(|server_name| server_name.to_string())
+        .collect()
+}
+
+fn format_status_table(status: &[ServerStatus]) -> Vec<String> {
+    let header = [
+        "ID",
+        "NAME",
+        "STATE",
+        "HOST",
+        "CONTAINER",
+        "PORT",
+        "PLAYERS",
+        "UPTIME",
+        "NEXT RESTART",
+        "RESTARTS",
+    ]
+    .map(|title| title.to_string());
+    let rows: Vec<_> = status
+        .iter()
+        .map(|server_status| {
+            let mut state = server_status.state.to_string();
+            if server_status.is_old {
+                state.push_str(" (old)");
+            }
+            if server_status.update_available {
+                state.push_str(" (update available)");
+            }
+
+            [
+                server_status.id.clone(),
+                server_status.name.clone(),
+                state,
+                server_status
+                    .host
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+                server_status
+                    .container_id
+                    .as_ref()
+                    .map(|container_id| container_id.chars().take(12).collect())
+                    .unwrap_or_else(|| "-".to_string()),
+                server_status
+                    .game_port
+                    .map(|game_port| game_port.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                server_status
+                    .players
+                    .as_ref()
+                    .map(|players| players.len().to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                server_status
+                    .uptime_seconds
+                    .map(format_duration)
+                    .unwrap_or_else(|| "-".to_string()),
+                server_status
+                    .next_restart_time
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                server_status.restart_counts.total().to_string(),
+            ]
+        })
+        .collect();
+
+    let mut column_widths = header.clone().map(|title| title.len());
+    for row in &rows {
+        for (column_width, cell) in column_widths.iter_mut().zip(row) {
+            *column_width = (*column_width).max(cell.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            row.iter()
+                .zip(column_widths)
+                .map(|(cell, column_width)| format!("{:width$}", cell, width = column_width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+fn format_duration(total_seconds: i64) -> String {
+    let days = total_seconds / 86400;
+    let hours = total_seconds % 86400 / 3600;
+    let minutes = total_seconds % 3600 / 60;
+    let seconds = total_seconds % 60;
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
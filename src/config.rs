@@ -1,12 +1,23 @@
 use linked_hash_map::LinkedHashMap;
+use regex::Regex;
 use serde::de::Visitor;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashSet;
 use std::fmt::Formatter;
+use std::net::SocketAddr;
 use std::ops::RangeInclusive;
 use std::path::Path;
 use std::str::FromStr;
 
+/// The name of the host used when the config doesn't have any `[[hosts]]`.
+pub const LOCAL_HOST_NAME: &str = "local";
+
+/// A Source engine `A2S_INFO` query, which is sent for UDP ready checks by default.
+const A2S_INFO_QUERY: &[u8] = b"\xFF\xFF\xFF\xFFTSource Engine Query\0";
+
+/// Keys that don't match any config option, kept so they can be reported instead of ignored.
+pub type UnknownKeys = LinkedHashMap<String, toml::Value>;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum GraphicsMode {
@@ -14,6 +25,64 @@ pub enum GraphicsMode {
     Software,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Immediate,
+    WhenEmpty,
+    AfterMatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImagePullPolicy {
+    Never,
+    IfMissing,
+    Always,
+    OnRestart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadyCheck {
+    None,
+    Log,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TerminateAction {
+    StopWraith,
+    StopAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeEngine {
+    Auto,
+    Docker,
+    Podman,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationFormat {
+    Json,
+    Discord,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationEvent {
+    Crash,
+    CrashLooping,
+    ScheduledRestart,
+    StartFailed,
+    PortsExhausted,
+    RolloutFailed,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum PrivateLobbyPlayerPermissions {
@@ -187,6 +256,7 @@ impl PlaylistOverrides {
 #[derive(Debug, Clone, PartialEq)]
 pub struct FilledGameConfig {
     pub docker_image: String,
+    pub image_pull_policy: ImagePullPolicy,
     pub game_dir: String,
 
     pub description: String,
@@ -207,8 +277,28 @@ pub struct FilledGameConfig {
     pub mods: HashSet<String>,
 
     pub logs_dir: String,
+    pub logs_max_files: u32,
+    pub logs_max_age_days: Option<f64>,
+    pub logs_max_total_bytes: Option<u64>,
+    pub logs_compress: bool,
+    pub logs_rotate_bytes: Option<u64>,
     pub graphics_mode: GraphicsMode,
     pub restart_schedule: Option<cron_clock::Schedule>,
+    pub restart_policy: RestartPolicy,
+    pub match_start_log_pattern: Option<LogPattern>,
+    pub match_end_log_pattern: Option<LogPattern>,
+    pub restart_max_wait_seconds: f64,
+    pub restart_warning_minutes: Vec<u32>,
+    pub restart_warning_command: String,
+    pub restart_backoff_initial_seconds: f64,
+    pub restart_backoff_max_seconds: f64,
+    pub restart_backoff_failure_limit: u32,
+    pub restart_backoff_window_seconds: f64,
+    pub stop_timeout_seconds: f64,
+    pub ready_check: ReadyCheck,
+    pub ready_log_pattern: Option<LogPattern>,
+    pub ready_udp_payload: Vec<u8>,
+    pub start_timeout_seconds: f64,
     pub perf_memory_limit_bytes: Option<i64>,
     pub perf_virtual_memory_limit_bytes: Option<i64>,
     pub perf_cpus: Option<f64>,
@@ -231,6 +321,7 @@ pub struct FilledGameConfig {
 #[serde(rename_all = "kebab-case")]
 pub struct GameConfig {
     pub docker_image: Option<String>,
+    pub image_pull_policy: Option<ImagePullPolicy>,
     pub game_dir: Option<String>,
 
     pub description: Option<String>,
@@ -252,8 +343,28 @@ pub struct GameConfig {
     pub mods: HashSet<String>,
 
     pub logs_dir: Option<String>,
+    pub logs_max_files: Option<u32>,
+    pub logs_max_age_days: Option<f64>,
+    pub logs_max_total_bytes: Option<u64>,
+    pub logs_compress: Option<bool>,
+    pub logs_rotate_bytes: Option<u64>,
     pub graphics_mode: Option<GraphicsMode>,
     pub restart_schedule: Option<CronSchedule>,
+    pub restart_policy: Option<RestartPolicy>,
+    pub match_start_log_pattern: Option<LogPattern>,
+    pub match_end_log_pattern: Option<LogPattern>,
+    pub restart_max_wait_seconds: Option<f64>,
+    pub restart_warning_minutes: Option<Vec<u32>>,
+    pub restart_warning_command: Option<String>,
+    pub restart_backoff_initial_seconds: Option<f64>,
+    pub restart_backoff_max_seconds: Option<f64>,
+    pub restart_backoff_failure_limit: Option<u32>,
+    pub restart_backoff_window_seconds: Option<f64>,
+    pub stop_timeout_seconds: Option<f64>,
+    pub ready_check: Option<ReadyCheck>,
+    pub ready_log_pattern: Option<LogPattern>,
+    pub ready_udp_payload: Option<HexBytes>,
+    pub start_timeout_seconds: Option<f64>,
     pub perf_memory_limit_bytes: Option<i64>,
     pub perf_virtual_memory_limit_bytes: Option<i64>,
     pub perf_cpus: Option<f64>,
@@ -279,6 +390,9 @@ pub struct GameConfig {
 
     #[serde(default)]
     pub extra_binds: Vec<String>,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
 }
 
 impl GameConfig {
@@ -300,6 +414,7 @@ impl GameConfig {
 
         GameConfig {
             docker_image: self.docker_image.or(other.docker_image),
+            image_pull_policy: self.image_pull_policy.or(other.image_pull_policy),
             game_dir: self.game_dir.or(other.game_dir),
 
             description: self.description.or(other.description),
@@ -324,8 +439,44 @@ impl GameConfig {
             mods,
 
             logs_dir: self.logs_dir.or(other.logs_dir),
+            logs_max_files: self.logs_max_files.or(other.logs_max_files),
+            logs_max_age_days: self.logs_max_age_days.or(other.logs_max_age_days),
+            logs_max_total_bytes: self.logs_max_total_bytes.or(other.logs_max_total_bytes),
+            logs_compress: self.logs_compress.or(other.logs_compress),
+            logs_rotate_bytes: self.logs_rotate_bytes.or(other.logs_rotate_bytes),
             graphics_mode: self.graphics_mode.or(other.graphics_mode),
             restart_schedule: self.restart_schedule.or(other.restart_schedule),
+            restart_policy: self.restart_policy.or(other.restart_policy),
+            match_start_log_pattern: self
+                .match_start_log_pattern
+                .or(other.match_start_log_pattern),
+            match_end_log_pattern: self.match_end_log_pattern.or(other.match_end_log_pattern),
+            restart_max_wait_seconds: self
+                .restart_max_wait_seconds
+                .or(other.restart_max_wait_seconds),
+            restart_warning_minutes: self
+                .restart_warning_minutes
+                .or(other.restart_warning_minutes),
+            restart_warning_command: self
+                .restart_warning_command
+                .or(other.restart_warning_command),
+            restart_backoff_initial_seconds: self
+                .restart_backoff_initial_seconds
+                .or(other.restart_backoff_initial_seconds),
+            restart_backoff_max_seconds: self
+                .restart_backoff_max_seconds
+                .or(other.restart_backoff_max_seconds),
+            restart_backoff_failure_limit: self
+                .restart_backoff_failure_limit
+                .or(other.restart_backoff_failure_limit),
+            restart_backoff_window_seconds: self
+                .restart_backoff_window_seconds
+                .or(other.restart_backoff_window_seconds),
+            stop_timeout_seconds: self.stop_timeout_seconds.or(other.stop_timeout_seconds),
+            ready_check: self.ready_check.or(other.ready_check),
+            ready_log_pattern: self.ready_log_pattern.or(other.ready_log_pattern),
+            ready_udp_payload: self.ready_udp_payload.or(other.ready_udp_payload),
+            start_timeout_seconds: self.start_timeout_seconds.or(other.start_timeout_seconds),
             perf_memory_limit_bytes: self
                 .perf_memory_limit_bytes
                 .or(other.perf_memory_limit_bytes),
@@ -347,12 +498,14 @@ impl GameConfig {
             extra_vars,
             extra_args,
             extra_binds,
+            unknown_keys: self.unknown_keys,
         }
     }
 
     pub fn fill(self, id: &str, config_dir: &Path) -> FilledGameConfig {
         FilledGameConfig {
             docker_image: self.docker_image.unwrap_or("".to_string()),
+            image_pull_policy: self.image_pull_policy.unwrap_or(ImagePullPolicy::IfMissing),
             game_dir: config_dir
                 .join(self.game_dir.as_ref().map(|s| s as &str).unwrap_or(""))
                 .to_string_lossy()
@@ -392,8 +545,38 @@ impl GameConfig {
                 )
                 .to_string_lossy()
                 .to_string(),
+            logs_max_files: self.logs_max_files.unwrap_or(5),
+            logs_max_age_days: self.logs_max_age_days,
+            logs_max_total_bytes: self.logs_max_total_bytes,
+            logs_compress: self.logs_compress.unwrap_or(false),
+            logs_rotate_bytes: self.logs_rotate_bytes,
             graphics_mode: self.graphics_mode.unwrap_or(GraphicsMode::Default),
             restart_schedule: self.restart_schedule.map(|schedule| schedule.0),
+            restart_policy: self.restart_policy.unwrap_or(RestartPolicy::Immediate),
+            match_start_log_pattern: self.match_start_log_pattern,
+            match_end_log_pattern: self.match_end_log_pattern,
+            restart_max_wait_seconds: self.restart_max_wait_seconds.unwrap_or(1800.),
+            restart_warning_minutes: self.restart_warning_minutes.unwrap_or(vec![10, 5, 1]),
+            restart_warning_command: self
+                .restart_warning_command
+                .unwrap_or_else(|| "say Server will restart in {minutes} minute(s)".to_string()),
+            restart_backoff_initial_seconds: self.restart_backoff_initial_seconds.unwrap_or(2.),
+            restart_backoff_max_seconds: self.restart_backoff_max_seconds.unwrap_or(300.),
+            restart_backoff_failure_limit: self.restart_backoff_failure_limit.unwrap_or(5),
+            restart_backoff_window_seconds: self.restart_backoff_window_seconds.unwrap_or(600.),
+            stop_timeout_seconds: self.stop_timeout_seconds.unwrap_or(10.),
+            // Only wait for a log line if there's a pattern for it, since Northstar doesn't log one
+            // line that reliably means it's ready
+            ready_check: self.ready_check.unwrap_or(match self.ready_log_pattern {
+                Some(_) => ReadyCheck::Log,
+                None => ReadyCheck::None,
+            }),
+            ready_log_pattern: self.ready_log_pattern,
+            ready_udp_payload: self
+                .ready_udp_payload
+                .map(|payload| payload.0)
+                .unwrap_or_else(|| A2S_INFO_QUERY.to_vec()),
+            start_timeout_seconds: self.start_timeout_seconds.unwrap_or(300.),
             perf_memory_limit_bytes: self.perf_memory_limit_bytes,
             perf_virtual_memory_limit_bytes: self.perf_virtual_memory_limit_bytes,
             perf_cpus: self.perf_cpus,
@@ -418,7 +601,9 @@ impl GameConfig {
 #[derive(Debug, Clone, PartialEq)]
 pub struct FilledInstanceConfig {
     pub name: String,
+    pub host: Option<String>,
     pub game_port: Option<u16>,
+    pub auth_port: Option<u16>,
     pub game_config: FilledGameConfig,
 }
 
@@ -426,7 +611,9 @@ pub struct FilledInstanceConfig {
 #[serde(rename_all = "kebab-case")]
 pub struct InstanceConfig {
     pub name: String,
+    pub host: Option<String>,
     pub game_port: Option<u16>,
+    pub auth_port: Option<u16>,
 
     #[serde(flatten)]
     pub game_config: GameConfig,
@@ -445,25 +632,270 @@ impl InstanceConfig {
             .fill(id, config_dir);
         FilledInstanceConfig {
             name: self.name,
+            host: self.host,
             game_port: self.game_port,
+            auth_port: self.auth_port,
             game_config,
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApiConfig {
+    pub bind: SocketAddr,
+    pub token: Option<String>,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MetricsConfig {
+    pub bind: SocketAddr,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RuntimeConfig {
+    #[serde(default = "default_runtime_engine")]
+    pub engine: RuntimeEngine,
+    pub socket: Option<String>,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            engine: default_runtime_engine(),
+            socket: None,
+            unknown_keys: UnknownKeys::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DockerConfig {
+    pub host: Option<String>,
+    pub cert_dir: Option<String>,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: f64,
+
+    #[serde(default = "default_request_timeout_seconds")]
+    pub request_timeout_seconds: f64,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        DockerConfig {
+            host: None,
+            cert_dir: None,
+            ca_cert: None,
+            client_cert: None,
+            client_key: None,
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            request_timeout_seconds: default_request_timeout_seconds(),
+            unknown_keys: UnknownKeys::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RolloutConfig {
+    #[serde(default = "default_rollout_concurrency")]
+    pub concurrency: usize,
+
+    #[serde(default = "default_rollout_ready_timeout_seconds")]
+    pub ready_timeout_seconds: f64,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        RolloutConfig {
+            concurrency: default_rollout_concurrency(),
+            ready_timeout_seconds: default_rollout_ready_timeout_seconds(),
+            unknown_keys: UnknownKeys::new(),
+        }
+    }
+}
+
+/// A machine that servers can run on, from a `[[hosts]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HostConfig {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub runtime: RuntimeConfig,
+
+    #[serde(flatten)]
+    pub docker: DockerConfig,
+
+    pub game_ports: Option<RangeInclusive<u16>>,
+    pub auth_ports: Option<RangeInclusive<u16>>,
+    pub capacity: Option<usize>,
+}
+
+impl HostConfig {
+    /// Each flattened table sees every key the host didn't take itself, so a key is only unknown
+    /// if neither of them knows it either.
+    pub fn unknown_keys(&self) -> UnknownKeys {
+        self.runtime
+            .unknown_keys
+            .iter()
+            .filter(|(key, _)| self.docker.unknown_keys.contains_key(*key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotificationConfig {
+    pub url: String,
+
+    #[serde(default = "default_notification_format")]
+    pub format: NotificationFormat,
+
+    #[serde(default = "default_notification_events")]
+    pub events: Vec<NotificationEvent>,
+
+    #[serde(default = "default_notification_rate_limit_count")]
+    pub rate_limit_count: u32,
+
+    #[serde(default = "default_notification_rate_limit_seconds")]
+    pub rate_limit_seconds: f64,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    #[serde(default = "default_instance_id")]
+    pub instance_id: String,
+
     #[serde(default = "default_poll_seconds")]
     pub poll_seconds: f64,
 
     #[serde(default = "default_game_ports")]
     pub game_ports: RangeInclusive<u16>,
 
+    pub auth_ports: Option<RangeInclusive<u16>>,
+
+    #[serde(default = "default_terminate_action")]
+    pub terminate_action: TerminateAction,
+
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+
+    #[serde(default)]
+    pub docker: DockerConfig,
+
+    #[serde(default)]
+    pub hosts: Vec<HostConfig>,
+
+    #[serde(default)]
+    pub rollout: RolloutConfig,
+
+    pub api: Option<ApiConfig>,
+
+    pub metrics: Option<MetricsConfig>,
+
+    #[serde(default)]
+    pub notifications: Vec<NotificationConfig>,
+
     #[serde(default)]
     pub defaults: GameConfig,
 
     pub servers: LinkedHashMap<String, InstanceConfig>,
+
+    #[serde(flatten)]
+    pub unknown_keys: UnknownKeys,
+}
+
+impl Config {
+    /// Finds any keys that didn't match a config option, as (table, key) pairs.
+    pub fn unknown_keys(&self) -> Vec<(String, String)> {
+        let host_unknown_keys: Vec<_> = self.hosts.iter().map(HostConfig::unknown_keys).collect();
+        let mut table_keys = vec![
+            (String::new(), &self.unknown_keys),
+            ("runtime".to_string(), &self.runtime.unknown_keys),
+            ("docker".to_string(), &self.docker.unknown_keys),
+            ("rollout".to_string(), &self.rollout.unknown_keys),
+        ];
+        for unknown_keys in &host_unknown_keys {
+            table_keys.push(("hosts".to_string(), unknown_keys));
+        }
+        if let Some(api_config) = &self.api {
+            table_keys.push(("api".to_string(), &api_config.unknown_keys));
+        }
+        if let Some(metrics_config) = &self.metrics {
+            table_keys.push(("metrics".to_string(), &metrics_config.unknown_keys));
+        }
+        for notification_config in &self.notifications {
+            table_keys.push((
+                "notifications".to_string(),
+                &notification_config.unknown_keys,
+            ));
+        }
+        table_keys.push(("defaults".to_string(), &self.defaults.unknown_keys));
+        for (id, instance_config) in &self.servers {
+            table_keys.push((
+                format!("servers.{}", id),
+                &instance_config.game_config.unknown_keys,
+            ));
+        }
+
+        table_keys
+            .into_iter()
+            .flat_map(|(table, unknown_keys)| {
+                unknown_keys
+                    .keys()
+                    .map(move |key| (table.clone(), key.clone()))
+            })
+            .collect()
+    }
+
+    /// The hosts that servers can run on. Without any `[[hosts]]` entries, this is a single host
+    /// named "local" using the `[runtime]` and `[docker]` sections.
+    pub fn host_configs(&self) -> Vec<HostConfig> {
+        if !self.hosts.is_empty() {
+            return self.hosts.clone();
+        }
+
+        vec![HostConfig {
+            name: LOCAL_HOST_NAME.to_string(),
+            runtime: self.runtime.clone(),
+            docker: self.docker.clone(),
+            game_ports: None,
+            auth_ports: None,
+            capacity: None,
+        }]
+    }
+}
+
+fn default_instance_id() -> String {
+    "default".to_string()
 }
 
 fn default_poll_seconds() -> f64 {
@@ -474,6 +906,53 @@ fn default_game_ports() -> RangeInclusive<u16> {
     37015..=37020
 }
 
+fn default_terminate_action() -> TerminateAction {
+    TerminateAction::StopWraith
+}
+
+fn default_runtime_engine() -> RuntimeEngine {
+    RuntimeEngine::Auto
+}
+
+fn default_connect_timeout_seconds() -> f64 {
+    10.
+}
+
+fn default_request_timeout_seconds() -> f64 {
+    120.
+}
+
+fn default_rollout_concurrency() -> usize {
+    1
+}
+
+fn default_rollout_ready_timeout_seconds() -> f64 {
+    300.
+}
+
+fn default_notification_format() -> NotificationFormat {
+    NotificationFormat::Json
+}
+
+fn default_notification_events() -> Vec<NotificationEvent> {
+    vec![
+        NotificationEvent::Crash,
+        NotificationEvent::CrashLooping,
+        NotificationEvent::ScheduledRestart,
+        NotificationEvent::StartFailed,
+        NotificationEvent::PortsExhausted,
+        NotificationEvent::RolloutFailed,
+    ]
+}
+
+fn default_notification_rate_limit_count() -> u32 {
+    5
+}
+
+fn default_notification_rate_limit_seconds() -> f64 {
+    60.
+}
+
 #[derive(Debug, Clone)]
 pub struct CronSchedule(pub cron_clock::Schedule);
 
@@ -504,3 +983,85 @@ impl<'de> Visitor<'de> for CronScheduleVisitor {
             .map_err(|e| serde::de::Error::custom(e))
     }
 }
+
+/// A regex from the config, compared by its pattern.
+#[derive(Debug, Clone)]
+pub struct LogPattern(pub Regex);
+
+impl PartialEq for LogPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl<'de> Deserialize<'de> for LogPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(LogPatternVisitor)
+    }
+}
+
+struct LogPatternVisitor;
+
+impl<'de> Visitor<'de> for LogPatternVisitor {
+    type Value = LogPattern;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "a regular expression")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Regex::new(v)
+            .map(LogPattern)
+            .map_err(|e| serde::de::Error::custom(e))
+    }
+}
+
+/// Bytes written as a hex string in the config, e.g. `"ffffffff54"`.
+#[derive(Debug, Clone)]
+pub struct HexBytes(pub Vec<u8>);
+
+impl<'de> Deserialize<'de> for HexBytes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(HexBytesVisitor)
+    }
+}
+
+struct HexBytesVisitor;
+
+impl<'de> Visitor<'de> for HexBytesVisitor {
+    type Value = HexBytes;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "a hex string")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        let digits: Vec<_> = v.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.is_empty() || digits.len() % 2 != 0 {
+            return Err(serde::de::Error::custom(
+                "expected an even number of hex digits",
+            ));
+        }
+        digits
+            .chunks(2)
+            .map(|pair| {
+                let pair: String = pair.iter().collect();
+                u8::from_str_radix(&pair, 16)
+                    .map_err(|_| serde::de::Error::custom(format!("{} isn't a hex byte", pair)))
+            })
+            .collect::<Result<_, _>>()
+            .map(HexBytes)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::metrics::record_docker_error;
+use crate::runtime::ContainerRuntime;
+use bollard::container::AttachContainerResults;
+use futures::StreamExt;
+use log::debug;
+use std::error::Error;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, Instant};
+
+/// How long the server can be quiet before we assume it has finished replying to a command.
+const REPLY_QUIET_TIME: Duration = Duration::from_secs(1);
+
+/// The longest we'll collect a reply for, in case the server is constantly logging.
+const REPLY_MAX_TIME: Duration = Duration::from_secs(5);
+
+pub type ConsoleReply = Result<Vec<String>, String>;
+
+/// Writes a line to the dedicated server console through the container's stdin, and returns
+/// whatever the server logs in response.
+pub async fn send_console_command(
+    runtime: &dyn ContainerRuntime,
+    container_id: &str,
+    command: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut attach_results = attach_and_write(runtime, container_id, command).await?;
+
+    let mut reply = Vec::new();
+    let deadline = Instant::now() + REPLY_MAX_TIME;
+    while Instant::now() < deadline {
+        let output = match timeout(REPLY_QUIET_TIME, attach_results.output.next()).await {
+            Ok(Some(output)) => output.inspect_err(record_docker_error)?,
+            // The stream has ended or the server has gone quiet
+            Ok(None) | Err(_) => break,
+        };
+
+        let stripped_output = strip_ansi_escapes::strip(output.into_bytes())?;
+        reply.extend(
+            String::from_utf8_lossy(&stripped_output)
+                .lines()
+                .map(|line| line.trim_end().to_string())
+                .filter(|line| !line.is_empty()),
+        );
+    }
+
+    Ok(reply)
+}
+
+/// Writes a line to the dedicated server console without waiting for a reply.
+pub async fn write_console_command(
+    runtime: &dyn ContainerRuntime,
+    container_id: &str,
+    command: &str,
+) -> Result<(), Box<dyn Error>> {
+    attach_and_write(runtime, container_id, command).await?;
+    Ok(())
+}
+
+async fn attach_and_write(
+    runtime: &dyn ContainerRuntime,
+    container_id: &str,
+    command: &str,
+) -> Result<AttachContainerResults, Box<dyn Error>> {
+    let mut attach_results = runtime
+        .attach_container(container_id)
+        .await
+        .inspect_err(record_docker_error)?;
+
+    debug!("Sending console command to {}: {}", container_id, command);
+    attach_results
+        .input
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    attach_results.input.flush().await?;
+    Ok(attach_results)
+}
@@ -0,0 +1,104 @@
+use crate::hosts::Host;
+use crate::metrics::record_docker_error;
+use bollard::models::EventMessage;
+use futures::StreamExt;
+use log::{debug, warn};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::sleep;
+
+pub const CONTAINER_NAME_PREFIX: &str = "r2wraith-";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerEventKind {
+    Died,
+    Stopped,
+}
+
+impl ContainerEventKind {
+    fn from_action(action: &str) -> Option<Self> {
+        match action {
+            // "oom" isn't included, since the container can survive the OOM killer, and "die" is
+            // always sent if it doesn't
+            "die" => Some(ContainerEventKind::Died),
+            "stop" => Some(ContainerEventKind::Stopped),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ContainerEventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerEventKind::Died => write!(f, "died"),
+            ContainerEventKind::Stopped => write!(f, "was stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContainerEvent {
+    pub host_name: String,
+    pub container_id: String,
+    pub kind: ContainerEventKind,
+}
+
+/// Spawns a task that follows the host runtime's event stream and forwards lifecycle events for
+/// R2Wraith containers. The stream is reconnected if it drops, so the poll in the server loop only
+/// needs to catch anything that happened while disconnected.
+pub fn spawn_container_watcher(host: Arc<Host>, sender: UnboundedSender<ContainerEvent>) {
+    tokio::spawn(async move {
+        loop {
+            let mut event_stream = host.runtime.events();
+            debug!("Watching container events on {}", host.name);
+
+            while let Some(maybe_event) = event_stream.next().await {
+                let event = match maybe_event {
+                    Ok(event) => event,
+                    Err(why) => {
+                        record_docker_error(&why);
+                        warn!("Failed to read container events on {}: {}", host.name, why);
+                        break;
+                    }
+                };
+
+                if let Some(container_event) = get_container_event(&host.name, event) {
+                    if sender.send(container_event).is_err() {
+                        // The server loop has exited
+                        return;
+                    }
+                }
+            }
+
+            warn!(
+                "Lost connection to container events on {}, reconnecting in {} seconds",
+                host.name,
+                RECONNECT_DELAY.as_secs()
+            );
+            sleep(RECONNECT_DELAY).await;
+        }
+    });
+}
+
+fn get_container_event(host_name: &str, event: EventMessage) -> Option<ContainerEvent> {
+    let kind = ContainerEventKind::from_action(event.action.as_deref()?)?;
+    let actor = event.actor?;
+    let is_wraith_container = actor
+        .attributes
+        .as_ref()
+        .and_then(|attributes| attributes.get("name"))
+        .map(|name| name.starts_with(CONTAINER_NAME_PREFIX))
+        .unwrap_or(false);
+    if !is_wraith_container {
+        return None;
+    }
+
+    Some(ContainerEvent {
+        host_name: host_name.to_string(),
+        container_id: actor.id?,
+        kind,
+    })
+}
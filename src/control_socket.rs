@@ -0,0 +1,109 @@
+use crate::commands::CommandRunner;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ControlResponse {
+    ok: bool,
+    output: Vec<String>,
+}
+
+/// Listens for commands on a Unix socket. Each connection sends a single command line and gets
+/// a JSON [`ControlResponse`] back.
+pub fn spawn_control_socket(socket_path: &Path, command_runner: CommandRunner) -> io::Result<()> {
+    // Clean up a socket left behind by an R2Wraith that didn't exit cleanly, but not one that
+    // another R2Wraith is still listening on
+    if socket_path.exists() {
+        if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another R2Wraith is already listening on it",
+            ));
+        }
+        std::fs::remove_file(socket_path)?;
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o660))?;
+    info!("Listening for commands on {}", socket_path.display());
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(why) => {
+                    error!("Failed to accept control socket connection: {}", why);
+                    continue;
+                }
+            };
+
+            let command_runner = command_runner.clone();
+            tokio::spawn(async move {
+                if let Err(why) = handle_connection(stream, &command_runner).await {
+                    warn!("Control socket connection failed: {}", why);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle_connection(stream: UnixStream, command_runner: &CommandRunner) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut command = String::new();
+    if BufReader::new(reader).read_line(&mut command).await? == 0 {
+        // Closed without a command, like when another R2Wraith checks whether the socket is in use
+        return Ok(());
+    }
+    debug!("Received control command: {}", command.trim());
+
+    let output = command_runner.run(&command).await;
+    let response = serde_json::to_vec(&ControlResponse {
+        ok: !output.is_error,
+        output: output.lines,
+    })?;
+    writer.write_all(&response).await?;
+    writer.shutdown().await
+}
+
+/// Sends a command to a running R2Wraith and prints its output, returning the process exit code.
+pub async fn run_client(socket_path: &Path, command: &str) -> i32 {
+    let response: Result<ControlResponse, Box<dyn std::error::Error>> = async {
+        let mut stream = UnixStream::connect(socket_path).await?;
+        stream
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+        stream.shutdown().await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok(serde_json::from_slice(&response)?)
+    }
+    .await;
+
+    match response {
+        Ok(response) => {
+            for line in &response.output {
+                println!("{}", line);
+            }
+            if response.ok {
+                0
+            } else {
+                1
+            }
+        }
+        Err(why) => {
+            eprintln!(
+                "Failed to send command to R2Wraith at {}: {}",
+                socket_path.display(),
+                why
+            );
+            1
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::check::format_key_path;
+use crate::config::HostConfig;
+use crate::images::with_default_tag;
+use crate::runtime::{self, ContainerRuntime, Endpoint};
+use crate::Config;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Formatter};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A machine that servers can run on, through its own container runtime.
+pub struct Host {
+    pub name: String,
+    pub runtime: Arc<dyn ContainerRuntime>,
+    pub limits: HostLimits,
+    /// Where the host's game ports can be reached from here, for UDP ready checks.
+    pub game_address: String,
+    /// The ID each image name was last seen pointing to, so servers running an older image can
+    /// be told apart.
+    image_ids: Mutex<HashMap<String, String>>,
+}
+
+/// The ports and room a host has for servers, which is all that's needed to place them.
+#[derive(Debug, Clone)]
+pub struct HostLimits {
+    pub game_ports: RangeInclusive<u16>,
+    pub auth_ports: Option<RangeInclusive<u16>>,
+    /// The most servers that can run at once, if it's limited.
+    pub capacity: Option<usize>,
+}
+
+impl HostLimits {
+    /// Uses the top-level port ranges for any the host doesn't set.
+    pub fn new(host_config: &HostConfig, config: &Config) -> Self {
+        HostLimits {
+            game_ports: host_config
+                .game_ports
+                .clone()
+                .unwrap_or_else(|| config.game_ports.clone()),
+            auth_ports: host_config
+                .auth_ports
+                .clone()
+                .or_else(|| config.auth_ports.clone()),
+            capacity: host_config.capacity,
+        }
+    }
+}
+
+impl Debug for Host {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Host").field("name", &self.name).finish()
+    }
+}
+
+impl Host {
+    pub fn new(
+        host_config: &HostConfig,
+        config: &Config,
+        runtime: Arc<dyn ContainerRuntime>,
+    ) -> Self {
+        Host {
+            name: host_config.name.clone(),
+            runtime,
+            limits: HostLimits::new(host_config, config),
+            game_address: game_address(host_config),
+            image_ids: Mutex::default(),
+        }
+    }
+
+    pub fn record_image_id(&self, image: &str, image_id: &str) {
+        self.image_ids
+            .lock()
+            .unwrap()
+            .insert(with_default_tag(image), image_id.to_string());
+    }
+
+    /// The ID of the newest copy of the image on this host, if it's been checked since R2Wraith
+    /// started.
+    pub fn latest_image_id(&self, image: &str) -> Option<String> {
+        self.image_ids
+            .lock()
+            .unwrap()
+            .get(&with_default_tag(image))
+            .cloned()
+    }
+}
+
+/// Remote engines are reached over TCP, so their game ports are on the same machine. Anything else
+/// is a local socket.
+fn game_address(host_config: &HostConfig) -> String {
+    let docker_host = host_config
+        .docker
+        .host
+        .clone()
+        .or_else(|| std::env::var("DOCKER_HOST").ok());
+    let address = match docker_host.map(|docker_host| runtime::parse_docker_host(&docker_host)) {
+        Some(Ok(Endpoint::Tcp { address, .. })) => address,
+        _ => return "127.0.0.1".to_string(),
+    };
+    let address = address.split('/').next().unwrap_or(&address);
+    let address = match address.rsplit_once(':') {
+        Some((address, port)) if port.parse::<u16>().is_ok() => address,
+        _ => address,
+    };
+    address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string()
+}
+
+/// Connects to every host in the config. Servers can't be moved off a host that's missing, so
+/// any host failing to connect is an error.
+pub async fn connect_hosts(
+    config: &Config,
+    config_dir: &Path,
+) -> Result<Vec<Arc<Host>>, Box<dyn Error>> {
+    let mut hosts = Vec::new();
+    for host_config in config.host_configs() {
+        let connected_runtime =
+            runtime::connect(&host_config.runtime, &host_config.docker, config_dir).await;
+        let connected_runtime = match connected_runtime {
+            Ok(connected_runtime) => connected_runtime,
+            Err(why) if config.hosts.is_empty() => return Err(why),
+            Err(why) => return Err(format!("host {}: {}", host_config.name, why).into()),
+        };
+        if config.hosts.is_empty() {
+            info!("{}", connected_runtime.description);
+        } else {
+            info!(
+                "Host {} is {}",
+                host_config.name, connected_runtime.description
+            );
+        }
+
+        if let Some(unprivileged_port_start) = connected_runtime.unprivileged_port_start {
+            warn_privileged_ports(config, &host_config, unprivileged_port_start);
+        }
+        hosts.push(Arc::new(Host::new(
+            &host_config,
+            config,
+            connected_runtime.runtime,
+        )));
+    }
+    Ok(hosts)
+}
+
+/// Rootless runtimes can't bind ports below `net.ipv4.ip_unprivileged_port_start`, and the error
+/// from starting a server on one doesn't make that clear, so warn about them up front.
+fn warn_privileged_ports(config: &Config, host_config: &HostConfig, unprivileged_port_start: u16) {
+    let host_table = format!("hosts.{}", host_config.name);
+    let mut port_ranges = vec![match &host_config.game_ports {
+        Some(game_ports) => (
+            format_key_path(&host_table, "game-ports"),
+            game_ports.clone(),
+        ),
+        None => ("game-ports".to_string(), config.game_ports.clone()),
+    }];
+    match (&host_config.auth_ports, &config.auth_ports) {
+        (Some(auth_ports), _) => port_ranges.push((
+            format_key_path(&host_table, "auth-ports"),
+            auth_ports.clone(),
+        )),
+        (None, Some(auth_ports)) => {
+            port_ranges.push(("auth-ports".to_string(), auth_ports.clone()))
+        }
+        (None, None) => {}
+    }
+    for (id, instance_config) in &config.servers {
+        let can_run_here = match &instance_config.host {
+            Some(host_name) => host_name == &host_config.name,
+            None => true,
+        };
+        if !can_run_here {
+            continue;
+        }
+        for (key, port) in [
+            ("game-port", instance_config.game_port),
+            ("auth-port", instance_config.auth_port),
+        ] {
+            if let Some(port) = port {
+                port_ranges.push((format!("servers.{}.{}", id, key), port..=port));
+            }
+        }
+    }
+
+    for (key, port_range) in port_ranges {
+        if *port_range.start() < unprivileged_port_start {
+            warn!(
+                "{} includes ports below {}, which the rootless container runtime on {} can't bind",
+                key, unprivileged_port_start, host_config.name
+            );
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::config::ImagePullPolicy;
+use crate::hosts::Host;
+use crate::metrics::record_docker_error;
+use bollard::models::CreateImageInfo;
+use futures::StreamExt;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How often to log how far along a pull is.
+const PULL_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Adds the `latest` tag to image names without a tag or digest, since pulling a bare name would
+/// pull every tag.
+pub fn with_default_tag(image: &str) -> String {
+    let name = image.rsplit('/').next().unwrap_or(image);
+    if name.contains(':') || name.contains('@') {
+        image.to_string()
+    } else {
+        format!("{}:latest", image)
+    }
+}
+
+/// Whether an image can be used straight away, or has to be pulled first.
+pub enum ImageCheck {
+    Ready,
+    /// Holds the ID of the copy that's already on the host, if there is one.
+    NeedsPull(Option<String>),
+}
+
+/// Checks whether the image has to be pulled before a container is created from it, according to
+/// the pull policy. `is_planned_restart` is for restarts from the schedule or a command, which is
+/// when `on-restart` pulls.
+pub async fn check_image(
+    host: &Host,
+    image: &str,
+    pull_policy: ImagePullPolicy,
+    is_planned_restart: bool,
+) -> Result<ImageCheck, Box<dyn Error>> {
+    let local_image_id = inspect_image_id(host, image).await?;
+    let should_pull = match pull_policy {
+        ImagePullPolicy::Never => false,
+        ImagePullPolicy::IfMissing => local_image_id.is_none(),
+        ImagePullPolicy::Always => true,
+        ImagePullPolicy::OnRestart => is_planned_restart || local_image_id.is_none(),
+    };
+    if should_pull {
+        return Ok(ImageCheck::NeedsPull(local_image_id));
+    }
+
+    match local_image_id {
+        Some(image_id) => {
+            host.record_image_id(image, &image_id);
+            Ok(ImageCheck::Ready)
+        }
+        None => Err(format!(
+            "Image {} isn't on {} and image-pull-policy is \"never\"",
+            image, host.name
+        )
+        .into()),
+    }
+}
+
+/// Pulls the image in the background, since a big image or a slow registry would otherwise hold
+/// up every other server. If the pull fails and there's already a copy, that copy is used.
+pub fn spawn_pull(
+    host: Arc<Host>,
+    image: String,
+    local_image_id: Option<String>,
+) -> JoinHandle<Result<(), String>> {
+    tokio::spawn(async move {
+        if let Err(why) = pull_image(&host, &image).await {
+            let why = why.to_string();
+            match &local_image_id {
+                Some(_) => warn!(
+                    "Failed to pull {} on {}, using the copy that's already there: {}",
+                    image, host.name, why
+                ),
+                None => return Err(format!("Failed to pull {}: {}", image, why)),
+            }
+        }
+
+        let image_id = inspect_image_id(&host, &image)
+            .await
+            .map_err(|why| why.to_string())?
+            .ok_or_else(|| {
+                format!(
+                    "Image {} is missing on {} after pulling it",
+                    image, host.name
+                )
+            })?;
+        if local_image_id.is_some() && local_image_id.as_ref() != Some(&image_id) {
+            info!("Pulled a newer version of {} on {}", image, host.name);
+        }
+        host.record_image_id(&image, &image_id);
+        Ok(())
+    })
+}
+
+/// Gets the ID of the image on the host, or `None` if it hasn't been pulled.
+async fn inspect_image_id(host: &Host, image: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match host.runtime.inspect_image(&with_default_tag(image)).await {
+        Ok(inspect) => Ok(inspect.id),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Ok(None),
+        Err(why) => {
+            record_docker_error(&why);
+            Err(why.into())
+        }
+    }
+}
+
+async fn pull_image(host: &Host, image: &str) -> Result<(), Box<dyn Error>> {
+    info!("Pulling {} on {}", image, host.name);
+    let mut pull_stream = host.runtime.pull_image(&with_default_tag(image));
+    let mut progress = PullProgress::default();
+    let mut next_progress_time = Instant::now() + PULL_PROGRESS_INTERVAL;
+    while let Some(pull_info) = pull_stream.next().await {
+        let pull_info = pull_info.inspect_err(record_docker_error)?;
+        if let Some(why) = pull_info.error {
+            return Err(why.into());
+        }
+        if !progress.update(&pull_info) {
+            if let Some(status) = &pull_info.status {
+                debug!("Pulling {} on {}: {}", image, host.name, status);
+            }
+        }
+        if Instant::now() >= next_progress_time {
+            info!("Pulling {} on {}: {}", image, host.name, progress);
+            next_progress_time = Instant::now() + PULL_PROGRESS_INTERVAL;
+        }
+    }
+    info!("Pulled {} on {}: {}", image, host.name, progress);
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct LayerProgress {
+    downloaded_bytes: i64,
+    total_bytes: i64,
+    is_complete: bool,
+}
+
+/// Adds up the progress of each layer in a pull, since the runtime only reports them one by one.
+#[derive(Debug, Default)]
+struct PullProgress {
+    layers: HashMap<String, LayerProgress>,
+}
+
+impl PullProgress {
+    /// Returns false if the message isn't about a layer.
+    fn update(&mut self, pull_info: &CreateImageInfo) -> bool {
+        let (layer_id, status) = match (&pull_info.id, &pull_info.status) {
+            (Some(layer_id), Some(status)) => (layer_id, status.as_str()),
+            _ => return false,
+        };
+        let is_layer_status = matches!(
+            status,
+            "Pulling fs layer"
+                | "Waiting"
+                | "Downloading"
+                | "Verifying Checksum"
+                | "Download complete"
+                | "Extracting"
+                | "Pull complete"
+                | "Already exists"
+        );
+        if !is_layer_status {
+            return false;
+        }
+
+        let layer = self.layers.entry(layer_id.clone()).or_default();
+        match status {
+            "Downloading" => {
+                if let Some(progress_detail) = &pull_info.progress_detail {
+                    layer.downloaded_bytes = progress_detail.current.unwrap_or(0);
+                    layer.total_bytes = progress_detail.total.unwrap_or(0);
+                }
+            }
+            "Verifying Checksum" | "Download complete" | "Extracting" => {
+                layer.downloaded_bytes = layer.total_bytes;
+            }
+            "Pull complete" | "Already exists" => {
+                layer.downloaded_bytes = layer.total_bytes;
+                layer.is_complete = true;
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+impl Display for PullProgress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let complete_count = self
+            .layers
+            .values()
+            .filter(|layer| layer.is_complete)
+            .count();
+        let downloaded_bytes: i64 = self
+            .layers
+            .values()
+            .map(|layer| layer.downloaded_bytes)
+            .sum();
+        let total_bytes: i64 = self.layers.values().map(|layer| layer.total_bytes).sum();
+        write!(
+            f,
+            "{}/{} layers complete, {:.1}/{:.1} MB downloaded",
+            complete_count,
+            self.layers.len(),
+            downloaded_bytes as f64 / 1_000_000.,
+            total_bytes as f64 / 1_000_000.
+        )
+    }
+}
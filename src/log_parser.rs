@@ -0,0 +1,288 @@
+use regex::Regex;
+use std::sync::OnceLock;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameLogEventKind {
+    PlayerConnected(String),
+    PlayerDisconnected(String),
+    /// The server's match start log pattern matched.
+    MatchStarted,
+    /// The server's match end log pattern matched.
+    MatchEnded,
+    Crashed(String),
+    /// The server's ready log pattern matched, which is only sent once.
+    Ready,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameLogEvent {
+    pub server_id: String,
+    pub container_id: String,
+    pub kind: GameLogEventKind,
+}
+
+/// Patterns from the server's config, for lines that depend on the server's mods.
+#[derive(Debug, Default)]
+pub struct ConfigPatterns {
+    /// Cleared once it has matched, since the server only becomes ready once.
+    pub ready: Option<Regex>,
+    pub match_start: Option<Regex>,
+    pub match_end: Option<Regex>,
+}
+
+type EventBuilder = fn(&regex::Captures) -> GameLogEventKind;
+
+/// Lines from the dedicated server that we care about. Lines are matched after ANSI escapes have
+/// been stripped, and the first matching pattern wins. Only lines with fixed wording from the
+/// engine, Northstar's crash handler and Wine are matched here, see `sample.log`.
+fn line_patterns() -> &'static [(Regex, EventBuilder)] {
+    static LINE_PATTERNS: OnceLock<Vec<(Regex, EventBuilder)>> = OnceLock::new();
+    LINE_PATTERNS.get_or_init(|| {
+        let patterns: [(&str, EventBuilder); 3] = [
+            (r#"Client "(?P<player>[^"]+)" connected"#, |captures| {
+                GameLogEventKind::PlayerConnected(captures["player"].to_string())
+            }),
+            (r"Dropped (?P<player>.+?) from server", |captures| {
+                GameLogEventKind::PlayerDisconnected(captures["player"].to_string())
+            }),
+            (
+                r"(?P<message>(Northstar has crashed|wine: Unhandled (exception|page fault)).*)",
+                |captures| GameLogEventKind::Crashed(captures["message"].trim().to_string()),
+            ),
+        ];
+        patterns
+            .into_iter()
+            .map(|(pattern, build_event)| (Regex::new(pattern).unwrap(), build_event))
+            .collect()
+    })
+}
+
+fn parse_line(line: &str) -> Option<GameLogEventKind> {
+    line_patterns().iter().find_map(|(pattern, build_event)| {
+        pattern
+            .captures(line)
+            .map(|captures| build_event(&captures))
+    })
+}
+
+fn is_match(pattern: &Option<Regex>, line: &str) -> bool {
+    pattern
+        .as_ref()
+        .map(|pattern| pattern.is_match(line))
+        .unwrap_or(false)
+}
+
+/// Splits a server's log output into lines and sends an event for any that match a known pattern.
+/// Output can arrive in arbitrary chunks, so partial lines are buffered until they're complete.
+pub struct GameLogParser {
+    server_id: String,
+    container_id: String,
+    sender: UnboundedSender<GameLogEvent>,
+    config_patterns: ConfigPatterns,
+    partial_line: Vec<u8>,
+}
+
+impl GameLogParser {
+    pub fn new(
+        server_id: String,
+        container_id: String,
+        sender: UnboundedSender<GameLogEvent>,
+        config_patterns: ConfigPatterns,
+    ) -> Self {
+        GameLogParser {
+            server_id,
+            container_id,
+            sender,
+            config_patterns,
+            partial_line: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, output: &[u8]) {
+        let mut remaining_output = output;
+        while let Some(newline_index) = remaining_output.iter().position(|&byte| byte == b'\n') {
+            self.partial_line
+                .extend_from_slice(&remaining_output[..newline_index]);
+            remaining_output = &remaining_output[newline_index + 1..];
+
+            let line = std::mem::take(&mut self.partial_line);
+            self.parse(&String::from_utf8_lossy(&line));
+        }
+        self.partial_line.extend_from_slice(remaining_output);
+    }
+
+    /// Parses whatever is left over once the output has ended.
+    pub fn finish(&mut self) {
+        if !self.partial_line.is_empty() {
+            let line = std::mem::take(&mut self.partial_line);
+            self.parse(&String::from_utf8_lossy(&line));
+        }
+    }
+
+    fn parse(&mut self, line: &str) {
+        let line = line.trim_end_matches('\r');
+        if let Some(kind) = parse_line(line) {
+            self.send(kind);
+        }
+        if is_match(&self.config_patterns.match_start, line) {
+            self.send(GameLogEventKind::MatchStarted);
+        } else if is_match(&self.config_patterns.match_end, line) {
+            self.send(GameLogEventKind::MatchEnded);
+        }
+        if is_match(&self.config_patterns.ready, line) {
+            self.config_patterns.ready = None;
+            self.send(GameLogEventKind::Ready);
+        }
+    }
+
+    fn send(&self, kind: GameLogEventKind) {
+        let _ = self.sender.send(GameLogEvent {
+            server_id: self.server_id.clone(),
+            container_id: self.container_id.clone(),
+            kind,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    /// Pushes each chunk of output through a parser, returning the events it sent.
+    fn parse_chunks(chunks: &[&[u8]], config_patterns: ConfigPatterns) -> Vec<GameLogEventKind> {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut parser = GameLogParser::new(
+            "alpha".to_string(),
+            "container".to_string(),
+            sender,
+            config_patterns,
+        );
+        for chunk in chunks {
+            parser.push(chunk);
+        }
+        parser.finish();
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            kinds.push(event.kind);
+        }
+        kinds
+    }
+
+    fn ready_pattern(pattern: &str) -> ConfigPatterns {
+        ConfigPatterns {
+            ready: Some(Regex::new(pattern).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sample_log_is_parsed() {
+        let kinds = parse_chunks(
+            &[include_bytes!("log_parser/sample.log")],
+            ConfigPatterns::default(),
+        );
+        assert_eq!(
+            kinds,
+            vec![
+                GameLogEventKind::PlayerConnected("bob".to_string()),
+                GameLogEventKind::PlayerConnected("alice".to_string()),
+                GameLogEventKind::PlayerDisconnected("bob".to_string()),
+                GameLogEventKind::PlayerDisconnected("alice".to_string()),
+                GameLogEventKind::Crashed(
+                    "Northstar has crashed! a minidump has been written and exception info is available below:".to_string()
+                ),
+                GameLogEventKind::Crashed(
+                    "wine: Unhandled page fault on read access to 0000000000000000 at address 00000001401A2B3C (thread 0024), starting debugger...".to_string()
+                ),
+                GameLogEventKind::Crashed(
+                    "wine: Unhandled exception 0xc0000005 in thread 24 at address 00000001401A2B3C (thread 0024), starting debugger...".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn match_state_comes_from_config_patterns() {
+        let kinds = parse_chunks(
+            &[b"[MyMatchMod] match started\n[MyMatchMod] match ended\n"],
+            ConfigPatterns {
+                match_start: Some(Regex::new("match started").unwrap()),
+                match_end: Some(Regex::new("match ended").unwrap()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            kinds,
+            vec![GameLogEventKind::MatchStarted, GameLogEventKind::MatchEnded]
+        );
+    }
+
+    #[test]
+    fn lines_split_across_chunks_are_joined() {
+        let kinds = parse_chunks(
+            &[
+                b"[20:14:40] [info] Client \"bo",
+                b"b\" connected (1.2.3.4:37005).\n[20:20:03] [info] Dropped bob",
+                b" from server (Disconnect by user.)\n",
+            ],
+            ConfigPatterns::default(),
+        );
+        assert_eq!(
+            kinds,
+            vec![
+                GameLogEventKind::PlayerConnected("bob".to_string()),
+                GameLogEventKind::PlayerDisconnected("bob".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn carriage_returns_are_stripped() {
+        let kinds = parse_chunks(
+            &[b"[20:14:40] [info] Client \"bob\" connected (1.2.3.4:37005).\r\n"],
+            ready_pattern(r"\(1\.2\.3\.4:37005\)\.$"),
+        );
+        assert_eq!(
+            kinds,
+            vec![
+                GameLogEventKind::PlayerConnected("bob".to_string()),
+                GameLogEventKind::Ready,
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_partial_line_is_parsed_when_finished() {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut parser = GameLogParser::new(
+            "alpha".to_string(),
+            "container".to_string(),
+            sender,
+            ConfigPatterns::default(),
+        );
+        parser.push(b"Client \"bob\" connected\nDropped bob from server");
+        assert_eq!(
+            receiver.try_recv().unwrap().kind,
+            GameLogEventKind::PlayerConnected("bob".to_string())
+        );
+        assert!(receiver.try_recv().is_err());
+
+        parser.finish();
+        assert_eq!(
+            receiver.try_recv().unwrap().kind,
+            GameLogEventKind::PlayerDisconnected("bob".to_string())
+        );
+    }
+
+    #[test]
+    fn ready_is_only_sent_once() {
+        let kinds = parse_chunks(
+            &[b"[MyReadyMod] ready\n[MyReadyMod] ready\n"],
+            ready_pattern(r"\[MyReadyMod\] ready"),
+        );
+        assert_eq!(kinds, vec![GameLogEventKind::Ready]);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::config::FilledGameConfig;
+use crate::log_parser::GameLogParser;
+use crate::metrics::record_docker_error;
+use bollard::container::LogOutput;
+use chrono::{DateTime, Datelike, NaiveDateTime, Timelike, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{Stream, StreamExt};
+use log::{debug, info, warn};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+/// How often log retention is applied to every server, on top of whenever a server starts.
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Where a server's log files go, and how long they're kept around for.
+#[derive(Debug, Clone)]
+pub struct LogPolicy {
+    server_id: String,
+    logs_dir: PathBuf,
+    max_files: u32,
+    max_age: Option<Duration>,
+    max_total_bytes: Option<u64>,
+    compress: bool,
+    rotate_bytes: Option<u64>,
+}
+
+struct LogFileDetails {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+impl LogPolicy {
+    pub fn new(server_id: &str, game_config: &FilledGameConfig) -> Self {
+        LogPolicy {
+            server_id: server_id.to_string(),
+            logs_dir: PathBuf::from(&game_config.logs_dir),
+            max_files: game_config.logs_max_files,
+            max_age: game_config
+                .logs_max_age_days
+                .map(|days| Duration::from_secs_f64(days * 24. * 60. * 60.)),
+            max_total_bytes: game_config.logs_max_total_bytes,
+            compress: game_config.logs_compress,
+            rotate_bytes: game_config.logs_rotate_bytes,
+        }
+    }
+
+    /// Opens a new log file named after the server and the time, in the format
+    /// `servername YYYY-MM-DD hh-mm-ss.txt`.
+    pub async fn create_log_file(&self, time: DateTime<Utc>) -> Option<(File, PathBuf)> {
+        // Ensure the log directory exists
+        if let Err(why) = tokio::fs::create_dir_all(&self.logs_dir).await {
+            warn!(
+                "Failed to create log directory {}: {}",
+                self.logs_dir.display(),
+                why
+            );
+        }
+
+        let file_name = format!(
+            "{} {}-{}-{} {}-{}-{}",
+            self.server_id,
+            time.year(),
+            time.month(),
+            time.day(),
+            time.hour(),
+            time.minute(),
+            time.second()
+        );
+        let mut log_file_path = self.logs_dir.join(format!("{}.txt", file_name));
+
+        // Don't overwrite a file that was rotated within the same second
+        let mut file_index = 1;
+        while is_existing_file(&log_file_path).await
+            || is_existing_file(&gzip_path(&log_file_path)).await
+        {
+            file_index += 1;
+            log_file_path = self
+                .logs_dir
+                .join(format!("{} ({}).txt", file_name, file_index));
+        }
+
+        match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&log_file_path)
+            .await
+        {
+            Ok(file) => {
+                info!("Writing logs to {}", log_file_path.display());
+                Some((file, log_file_path))
+            }
+            Err(why) => {
+                warn!(
+                    "Failed to open log file {}: {}",
+                    log_file_path.display(),
+                    why
+                );
+                None
+            }
+        }
+    }
+
+    /// Deletes the oldest log files that go over the file count, age or total size limits. The
+    /// newest file is always kept, since it might still be being written to.
+    pub async fn apply_retention(&self) {
+        let mut log_files = match self.list_log_files().await {
+            Ok(log_files) => log_files,
+            Err(why) => {
+                warn!(
+                    "Failed to list log files in {}: {}",
+                    self.logs_dir.display(),
+                    why
+                );
+                return;
+            }
+        };
+        log_files.sort_by_key(|log_file| std::cmp::Reverse(log_file.modified));
+
+        let now = SystemTime::now();
+        let mut total_bytes = 0;
+        for (file_index, log_file) in log_files.iter().enumerate() {
+            let age = now.duration_since(log_file.modified).unwrap_or_default();
+            let is_expired = file_index > 0
+                && ((self.max_files > 0 && file_index >= self.max_files as usize)
+                    || self.max_age.map(|max_age| age > max_age).unwrap_or(false)
+                    || self
+                        .max_total_bytes
+                        .map(|max_total_bytes| total_bytes + log_file.size > max_total_bytes)
+                        .unwrap_or(false));
+            if !is_expired {
+                total_bytes += log_file.size;
+                continue;
+            }
+
+            match tokio::fs::remove_file(&log_file.path).await {
+                Ok(()) => info!("Removed old log file {}", log_file.path.display()),
+                Err(why) => warn!(
+                    "Failed to remove old log file {}: {}",
+                    log_file.path.display(),
+                    why
+                ),
+            }
+        }
+    }
+
+    async fn list_log_files(&self) -> Result<Vec<LogFileDetails>, Box<dyn Error>> {
+        let mut log_files = Vec::new();
+        let mut dir_entries = match tokio::fs::read_dir(&self.logs_dir).await {
+            Ok(dir_entries) => dir_entries,
+            // The server hasn't logged anything yet, e.g. if its image couldn't be pulled
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => return Ok(log_files),
+            Err(why) => return Err(why.into()),
+        };
+        while let Some(dir_entry) = dir_entries.next_entry().await? {
+            let file_name = dir_entry.file_name().to_string_lossy().to_string();
+            if !is_log_file_name(&file_name, &self.server_id) {
+                continue;
+            }
+
+            let metadata = dir_entry.metadata().await?;
+            if metadata.is_file() {
+                log_files.push(LogFileDetails {
+                    path: dir_entry.path(),
+                    modified: metadata.modified()?,
+                    size: metadata.len(),
+                });
+            }
+        }
+        Ok(log_files)
+    }
+
+    /// Called once nothing else will be written to a log file.
+    async fn finish_log_file(&self, log_file_path: PathBuf) {
+        if self.compress {
+            let compress_path = log_file_path.clone();
+            let compress_result = match tokio::task::spawn_blocking(move || {
+                compress_log_file(&compress_path)
+            })
+            .await
+            {
+                Ok(result) => result,
+                Err(why) => Err(why.into()),
+            };
+            match compress_result {
+                Ok(()) => debug!("Compressed log file {}", log_file_path.display()),
+                Err(why) => warn!(
+                    "Failed to compress log file {}: {}",
+                    log_file_path.display(),
+                    why
+                ),
+            }
+        }
+
+        self.apply_retention().await;
+    }
+}
+
+/// Pipes a container's output into its log file and the game log parser, starting a new file
+/// whenever the current one reaches `logs-rotate-bytes`.
+pub fn spawn_log_pipe(
+    mut log_stream: impl Stream<Item = Result<LogOutput, bollard::errors::Error>>
+        + Send
+        + Unpin
+        + 'static,
+    maybe_log_file: Option<(File, PathBuf)>,
+    log_policy: LogPolicy,
+    mut log_parser: GameLogParser,
+) {
+    tokio::spawn(async move {
+        let mut maybe_log_file = maybe_log_file;
+        let mut written_bytes = 0;
+
+        let maybe_res: Result<(), Box<dyn Error + Send + Sync>> = async {
+            while let Some(v) = log_stream.next().await {
+                let v = v.inspect_err(record_docker_error)?;
+                let stripped_v = strip_ansi_escapes::strip(v.into_bytes())?;
+                log_parser.push(&stripped_v);
+
+                let (log_file, log_file_path) = match &mut maybe_log_file {
+                    Some(log_file) => log_file,
+                    None => continue,
+                };
+                log_file.write_all(&stripped_v).await?;
+                written_bytes += stripped_v.len() as u64;
+
+                let should_rotate = log_policy
+                    .rotate_bytes
+                    .map(|rotate_bytes| written_bytes >= rotate_bytes)
+                    .unwrap_or(false);
+                if !should_rotate {
+                    continue;
+                }
+
+                // If a new file can't be opened, keep writing to the current one
+                if let Some((new_log_file, new_log_file_path)) =
+                    log_policy.create_log_file(Utc::now()).await
+                {
+                    log_file.flush().await?;
+                    *log_file = new_log_file;
+                    written_bytes = 0;
+                    let finished_log_file_path =
+                        std::mem::replace(log_file_path, new_log_file_path);
+                    log_policy.finish_log_file(finished_log_file_path).await;
+                }
+            }
+            if let Some((log_file, _)) = &mut maybe_log_file {
+                log_file.flush().await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(why) = maybe_res {
+            warn!("Failed to pipe logs: {}", why);
+        }
+        info!("Finished piping logs!");
+        log_parser.finish();
+
+        if let Some((log_file, log_file_path)) = maybe_log_file {
+            drop(log_file);
+            log_policy.finish_log_file(log_file_path).await;
+        }
+    });
+}
+
+fn compress_log_file(log_file_path: &Path) -> std::io::Result<()> {
+    let mut log_file = std::fs::File::open(log_file_path)?;
+    let modified = log_file.metadata()?.modified()?;
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(gzip_path(log_file_path))?,
+        Compression::default(),
+    );
+    std::io::copy(&mut log_file, &mut encoder)?;
+
+    // Keep the original modified time, since retention uses it to find the oldest files
+    encoder.finish()?.set_modified(modified)?;
+    std::fs::remove_file(log_file_path)
+}
+
+/// Whether a file name is one of the server's log files, as named by
+/// [`LogPolicy::create_log_file`]. Server IDs can contain spaces, so everything after the ID has
+/// to be the timestamp, otherwise server `a` would pick up the files of server `a b`.
+fn is_log_file_name(file_name: &str, server_id: &str) -> bool {
+    let file_stem = match file_name
+        .strip_suffix(".txt.gz")
+        .or_else(|| file_name.strip_suffix(".txt"))
+    {
+        Some(file_stem) => file_stem,
+        None => return false,
+    };
+    let timestamp = match file_stem
+        .strip_prefix(server_id)
+        .and_then(|rest| rest.strip_prefix(' '))
+    {
+        Some(timestamp) => timestamp,
+        None => return false,
+    };
+    // Files rotated within the same second have an index after the timestamp
+    let timestamp = match timestamp
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" ("))
+    {
+        Some((timestamp, file_index)) if file_index.parse::<u32>().is_ok() => timestamp,
+        _ => timestamp,
+    };
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H-%M-%S").is_ok()
+}
+
+fn gzip_path(log_file_path: &Path) -> PathBuf {
+    let mut gzip_path = log_file_path.as_os_str().to_owned();
+    gzip_path.push(".gz");
+    PathBuf::from(gzip_path)
+}
+
+async fn is_existing_file(path: &Path) -> bool {
+    tokio::fs::metadata(path).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_file_names_are_matched_exactly() {
+        assert!(is_log_file_name("a 2026-1-2 3-4-5.txt", "a"));
+        assert!(is_log_file_name("a 2026-1-2 3-4-5.txt.gz", "a"));
+        assert!(is_log_file_name("a 2026-1-2 3-4-5 (2).txt", "a"));
+        assert!(is_log_file_name("a b 2026-1-2 3-4-5.txt", "a b"));
+
+        // Another server whose ID starts with this one's
+        assert!(!is_log_file_name("a b 2026-1-2 3-4-5.txt", "a"));
+        assert!(!is_log_file_name("a b 2026-1-2 3-4-5 (2).txt.gz", "a"));
+        assert!(!is_log_file_name("ab 2026-1-2 3-4-5.txt", "a"));
+
+        assert!(!is_log_file_name("a 2026-1-2 3-4-5.log", "a"));
+        assert!(!is_log_file_name("a notes.txt", "a"));
+        assert!(!is_log_file_name("a 2026-1-2 3-4-5 (two).txt", "a"));
+    }
+}
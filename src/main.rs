@@ -1,15 +1,47 @@
+use crate::api::spawn_api_server;
+use crate::commands::{CommandRunner, ReloadFn};
 use crate::config::Config;
-use crate::server_cluster::{PollStatus, SerializedServer, Server, ServerCluster};
-use bollard::Docker;
+use crate::console::{send_console_command, ConsoleReply};
+use crate::container_watcher::{spawn_container_watcher, ContainerEvent};
+use crate::log_parser::GameLogEvent;
+use crate::metrics::spawn_metrics_server;
+use crate::notifications::Notifier;
+use crate::restart_backoff::seconds_to_duration;
+use crate::server_cluster::{PollStatus, SerializedServer, Server, ServerCluster, ServerStatus};
+use crate::signals::spawn_signal_handler;
 use log::{debug, error, info, warn, LevelFilter};
+use std::collections::HashSet;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::oneshot;
+use tokio::time::MissedTickBehavior;
 
+mod api;
 mod arg_builder;
+mod check;
+mod commands;
 mod config;
+mod console;
+mod container_watcher;
+#[cfg(unix)]
+mod control_socket;
+mod hosts;
+mod images;
+mod log_parser;
+mod logs;
+mod metrics;
+mod notifications;
+mod readiness;
+mod render;
+mod restart_backoff;
+mod rollout;
+mod runtime;
 mod server_cluster;
+mod signals;
 
 #[derive(Debug)]
 enum ReplCommand {
@@ -19,6 +51,16 @@ enum ReplCommand {
     StopOld,
     RestartAll,
     Restart(Vec<String>),
+    Stop(Vec<String>),
+    Start(Vec<String>),
+    GetStatus(oneshot::Sender<Vec<ServerStatus>>),
+    Console(String, String, oneshot::Sender<ConsoleReply>),
+    /// Servers to roll out, and the concurrency if it overrides the config.
+    Rollout(
+        Vec<String>,
+        Option<usize>,
+        oneshot::Sender<Result<(), String>>,
+    ),
 }
 
 #[tokio::main]
@@ -34,36 +76,29 @@ async fn main() {
     let exe_name = args.next().unwrap();
 
     let config_file_path = match args.next() {
+        Some(command) if command == "ctl" => {
+            std::process::exit(run_ctl(&exe_name, args.collect()).await);
+        }
+        Some(command) if command == "check" => {
+            std::process::exit(check::run_check(args.collect()));
+        }
+        Some(command) if command == "render" => {
+            std::process::exit(render::run_render(args.collect()));
+        }
         Some(path) => path,
         None => {
-            eprintln!("Usage: {} [path to config file]", exe_name);
-            eprintln!();
+            print_usage(&exe_name);
             std::process::exit(1);
         }
     };
 
     info!("R2Wraith {}", env!("CARGO_PKG_VERSION"));
 
-    let docker = match Docker::connect_with_local_defaults() {
-        Ok(docker) => docker,
-        Err(why) => {
-            error!("Failed to connect to Docker daemon: {}", why);
-            std::process::exit(1);
-        }
-    };
-    let docker_version = match docker.version().await {
-        Ok(version) => version.version.unwrap(),
-        Err(why) => {
-            error!("Failed to connect to Docker daemon: {}", why);
-            std::process::exit(1);
-        }
-    };
-    info!("Docker {}", docker_version);
-
     let full_config_path = std::env::current_dir().unwrap().join(&config_file_path);
     let restore_file_path = std::env::current_dir()
         .unwrap()
-        .join(&format!("{}.restore.json", config_file_path));
+        .join(format!("{}.restore.json", config_file_path));
+    let control_socket_path = get_control_socket_path(&config_file_path);
 
     let config = match load_config(&full_config_path) {
         Ok(config) => config,
@@ -74,6 +109,14 @@ async fn main() {
     };
 
     let config_dir = full_config_path.parent().unwrap().to_path_buf();
+    let hosts = match hosts::connect_hosts(&config, &config_dir).await {
+        Ok(hosts) => hosts,
+        Err(why) => {
+            error!("Failed to connect to container runtime: {}", why);
+            std::process::exit(1);
+        }
+    };
+
     let restore_serialized_servers = match load_serialized_servers(&restore_file_path) {
         Ok(servers) => {
             match std::fs::remove_file(&restore_file_path) {
@@ -93,28 +136,84 @@ async fn main() {
         }
     };
 
-    let mut server_cluster = ServerCluster::new();
+    let (game_log_event_sender, mut game_log_event_receiver) = unbounded_channel::<GameLogEvent>();
+    let mut server_cluster = ServerCluster::new(
+        hosts.clone(),
+        Notifier::spawn(config.notifications.clone()),
+        game_log_event_sender,
+    );
     server_cluster.load_servers(get_server_list_from_config(&config, &config_dir));
     server_cluster
-        .deserialize(restore_serialized_servers, &docker)
+        .deserialize(restore_serialized_servers, &config.instance_id)
         .await;
 
-    server_cluster.poll(&config, &docker).await;
+    server_cluster.poll(&config).await;
     info!("Ready!");
 
     let (repl_sender, mut repl_receiver) = unbounded_channel::<ReplCommand>();
+    let (container_event_sender, mut container_event_receiver) =
+        unbounded_channel::<ContainerEvent>();
+    for host in &hosts {
+        spawn_container_watcher(host.clone(), container_event_sender.clone());
+    }
+
+    let reload: ReloadFn = {
+        let config_path = full_config_path.clone();
+        let config_dir = config_dir.clone();
+        Arc::new(move || load_server_list(&config_path, &config_dir).map(ReplCommand::SetServers))
+    };
+    let signal_reload = reload.clone();
+    spawn_signal_handler(config.terminate_action, repl_sender.clone(), move || {
+        signal_reload()
+    });
+
+    let command_runner = CommandRunner::new(repl_sender.clone(), reload.clone());
+
+    #[cfg(unix)]
+    let is_control_socket_bound =
+        match control_socket::spawn_control_socket(&control_socket_path, command_runner.clone()) {
+            Ok(()) => true,
+            Err(why) => {
+                warn!(
+                    "Failed to open control socket at {}: {}",
+                    control_socket_path.display(),
+                    why
+                );
+                false
+            }
+        };
+
+    if let Some(api_config) = &config.api {
+        if let Err(why) = spawn_api_server(api_config, repl_sender.clone(), reload) {
+            error!("Failed to start API server on {}: {}", api_config.bind, why);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(metrics_config) = &config.metrics {
+        if let Err(why) = spawn_metrics_server(metrics_config, repl_sender.clone(), hosts.clone()) {
+            error!(
+                "Failed to start metrics server on {}: {}",
+                metrics_config.bind, why
+            );
+            std::process::exit(1);
+        }
+    }
 
     let server_join_handle = tokio::spawn(async move {
-        loop {
-            let receive_command = repl_receiver.recv();
-            let wait_timeout = tokio::time::sleep(Duration::from_secs_f64(config.poll_seconds));
+        let mut poll_interval = tokio::time::interval(Duration::from_secs_f64(config.poll_seconds));
+        poll_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut log_retention_interval = tokio::time::interval(logs::RETENTION_INTERVAL);
+        log_retention_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let stop_notify = server_cluster.stop_notify();
 
+        loop {
             tokio::select! {
-                command = receive_command => {
+                command = repl_receiver.recv() => {
                     match command {
                         Some(ReplCommand::StopAll) => {
                             debug!("Stopping all servers...");
-                            server_cluster.stop_all(&docker).await;
+                            server_cluster.stop_all().await;
                             break;
                         }
                         Some(ReplCommand::StopWraith) => {
@@ -129,93 +228,193 @@ async fn main() {
                             info!("Finished reloading config");
                         }
                         Some(ReplCommand::StopOld) => {
-                            server_cluster.stop_old(&docker).await;
+                            server_cluster.stop_old();
                         }
                         Some(ReplCommand::RestartAll) => {
-                            server_cluster.stop_all(&docker).await;
+                            server_cluster.restart_all();
                         }
                         Some(ReplCommand::Restart(servers)) => {
+                            server_cluster.restart(&servers);
+                        }
+                        Some(ReplCommand::Stop(servers)) => {
+                            server_cluster.hold(&servers);
+                        }
+                        Some(ReplCommand::Start(servers)) => {
                             for server_name in servers {
                                 match server_cluster.get_mut(&server_name) {
-                                    Some(server) => server.stop(&docker).await,
+                                    Some(server) => {
+                                        if !server.resume() {
+                                            info!("Server {} is not stopped or crash-looping", server_name);
+                                        }
+                                    }
                                     None => info!("Unknown server {}", server_name),
                                 }
                             }
                         }
+                        Some(ReplCommand::Rollout(servers, concurrency, reply_sender)) => {
+                            let concurrency = concurrency.unwrap_or(config.rollout.concurrency);
+                            let ready_timeout = seconds_to_duration(config.rollout.ready_timeout_seconds);
+                            let _ = reply_sender.send(server_cluster.start_rollout(servers, concurrency, ready_timeout));
+                        }
+                        Some(ReplCommand::GetStatus(status_sender)) => {
+                            // Nothing has changed, so there's no need to poll
+                            let _ = status_sender.send(server_cluster.status());
+                            continue;
+                        }
+                        Some(ReplCommand::Console(server_name, command, reply_sender)) => {
+                            let running_container = match server_cluster.get(&server_name) {
+                                Some(server) => server.host().zip(server.container_id()).map(|(host, container_id)| (host.runtime.clone(), container_id.to_string())),
+                                None => {
+                                    let _ = reply_sender.send(Err(format!("Unknown server {}", server_name)));
+                                    continue;
+                                }
+                            };
+                            let (runtime, container_id) = match running_container {
+                                Some(running_container) => running_container,
+                                None => {
+                                    let _ = reply_sender.send(Err(format!("Server {} is not running", server_name)));
+                                    continue;
+                                }
+                            };
+
+                            // Waiting for the reply takes a while, so don't hold up the server loop
+                            tokio::spawn(async move {
+                                let reply = send_console_command(runtime.as_ref(), &container_id, &command)
+                                    .await
+                                    .map_err(|why| format!("Failed to send console command to {}: {}", server_name, why));
+                                let _ = reply_sender.send(reply);
+                            });
+                            continue;
+                        }
                         None => break,
                     };
                 }
-                _ = wait_timeout => {}
+                Some(event) = container_event_receiver.recv() => {
+                    server_cluster.handle_container_event(&event);
+                }
+                Some(event) = game_log_event_receiver.recv() => {
+                    server_cluster.handle_game_log_event(&event);
+                    continue;
+                }
+                _ = poll_interval.tick() => {}
+                // Servers that have finished stopping are picked up by the poll
+                _ = stop_notify.notified() => {}
+                _ = log_retention_interval.tick() => {
+                    server_cluster.apply_log_retention().await;
+                    continue;
+                }
             }
 
-            if let PollStatus::DidWork = server_cluster.poll(&config, &docker).await {
+            if let PollStatus::DidWork = server_cluster.poll(&config).await {
                 info!("Done");
             }
         }
     });
 
     // Start REPL
-    let repl_join_handle = tokio::task::spawn_blocking(move || loop {
-        let mut buffer = String::new();
-        if let Err(_) = std::io::stdin().read_line(&mut buffer) {
-            continue;
-        }
-
-        let command = buffer.trim();
-
-        if command == "help" || command == "?" {
-            println!("< Available commands:");
-            println!("<   version - Display the version of R2Wraith");
-            println!("<   stopwraith - Stop R2Wraith, keeping servers running and writing a restore file");
-            println!("<   stopall - Shutdown all servers and stop R2Wraith");
-            println!("<   restartall - Restart all servers");
-            println!("<   restart [name] - Restart a server by name");
-            println!("<   reload - Reload the configuration file, starting any added servers");
-            println!("<   stopold - Stop any servers that have been removed from configuration");
-        } else if command == "version" {
-            println!("< R2Wraith {}", env!("CARGO_PKG_VERSION"));
-        } else if command == "stopwraith" {
-            repl_sender.send(ReplCommand::StopWraith).unwrap();
-            break;
-        } else if command == "stopall" {
-            repl_sender.send(ReplCommand::StopAll).unwrap();
-            break;
-        } else if command == "restartall" {
-            repl_sender.send(ReplCommand::RestartAll).unwrap();
-        } else if command == "reload" {
-            let new_config = match load_config(&full_config_path) {
-                Ok(config) => config,
-                Err(why) => {
-                    println!("< Failed to read config file: {}", why);
-                    continue;
-                }
+    tokio::spawn(async move {
+        let mut stdin_lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            let command = match stdin_lines.next_line().await {
+                Ok(Some(command)) => command,
+                // Stdin has been closed, e.g. when running under a service manager
+                Ok(None) => break,
+                Err(_) => continue,
             };
-            let new_servers = get_server_list_from_config(&new_config, &config_dir);
-            repl_sender
-                .send(ReplCommand::SetServers(new_servers))
-                .unwrap();
-        } else if command == "stopold" {
-            repl_sender.send(ReplCommand::StopOld).unwrap();
-        } else if command.starts_with("restart ") {
-            let server_names = command["restart ".len()..]
-                .trim()
-                .split_whitespace()
-                .map(|server_name| server_name.to_string())
-                .collect();
-            repl_sender
-                .send(ReplCommand::Restart(server_names))
-                .unwrap();
-        } else {
-            println!("< Unknown command: {}", command);
+
+            let output = command_runner.run(&command).await;
+            for line in &output.lines {
+                println!("< {}", line);
+            }
+            if output.is_stopping {
+                break;
+            }
         }
     });
 
     server_join_handle.await.unwrap();
-    repl_join_handle.await.unwrap();
+
+    // The socket could belong to another R2Wraith that was already listening on it
+    #[cfg(unix)]
+    if is_control_socket_bound {
+        if let Err(why) = std::fs::remove_file(&control_socket_path) {
+            warn!(
+                "Failed to remove control socket at {}: {}",
+                control_socket_path.display(),
+                why
+            );
+        }
+    }
+
+    // The REPL might still be waiting on stdin if we were stopped by a signal, so don't wait for it
+    std::process::exit(0);
+}
+
+fn print_usage(exe_name: &str) {
+    eprintln!("Usage: {} [path to config file]", exe_name);
+    eprintln!("       {} ctl [-c path to config file] [command]", exe_name);
+    eprintln!("       {} check [path to config file]", exe_name);
+    eprintln!(
+        "       {} render [path to config file] [server] [--json]",
+        exe_name
+    );
+    eprintln!();
+}
+
+fn get_control_socket_path(config_file_path: &str) -> PathBuf {
+    std::env::current_dir()
+        .unwrap()
+        .join(format!("{}.sock", config_file_path))
+}
+
+#[cfg(unix)]
+async fn run_ctl(exe_name: &str, args: Vec<String>) -> i32 {
+    let (config_file_path, command_args) = match args.as_slice() {
+        [flag, path, command_args @ ..] if flag == "-c" || flag == "--config" => {
+            (path.as_str(), command_args)
+        }
+        command_args => ("config.toml", command_args),
+    };
+    if command_args.is_empty() {
+        print_usage(exe_name);
+        return 1;
+    }
+
+    control_socket::run_client(
+        &get_control_socket_path(config_file_path),
+        &command_args.join(" "),
+    )
+    .await
+}
+
+#[cfg(not(unix))]
+async fn run_ctl(_exe_name: &str, _args: Vec<String>) -> i32 {
+    eprintln!("The ctl command is only supported on Unix systems");
+    1
 }
 
 fn load_config(config_path: &Path) -> Result<Config, Box<dyn Error>> {
-    Ok(toml::from_str(&std::fs::read_to_string(config_path)?)?)
+    let config: Config = toml::from_str(&std::fs::read_to_string(config_path)?)?;
+    for (table, key) in config.unknown_keys() {
+        warn!(
+            "Unknown config key {}, it will be ignored",
+            check::format_key_path(&table, &key)
+        );
+    }
+
+    // Servers and restore files refer to hosts by name, so they have to be unique
+    let mut host_names = HashSet::new();
+    for host_config in &config.hosts {
+        if !host_names.insert(&host_config.name) {
+            return Err(format!("there's more than one host called {}", host_config.name).into());
+        }
+    }
+    Ok(config)
+}
+
+fn load_server_list(config_path: &Path, config_dir: &Path) -> Result<Vec<Server>, Box<dyn Error>> {
+    let config = load_config(config_path)?;
+    Ok(get_server_list_from_config(&config, config_dir))
 }
 
 fn load_serialized_servers(restore_path: &Path) -> Result<Vec<SerializedServer>, Box<dyn Error>> {
@@ -229,7 +428,7 @@ fn store_serialized_servers(
     server_cluster: &ServerCluster,
 ) -> Result<(), Box<dyn Error>> {
     let serialized_servers = serde_json::to_string(&server_cluster.serialize())?;
-    std::fs::write(&restore_path, serialized_servers)?;
+    std::fs::write(restore_path, serialized_servers)?;
     Ok(())
 }
 
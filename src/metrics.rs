@@ -0,0 +1,361 @@
+use crate::config::MetricsConfig;
+use crate::hosts::Host;
+use crate::server_cluster::{ServerStateKind, ServerStatus};
+use crate::ReplCommand;
+use bollard::container::Stats;
+use futures::StreamExt;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{error, info};
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+static DOCKER_API_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a failed Docker API call. Containers going missing is expected whenever a server stops,
+/// so "not found" responses aren't counted.
+pub fn record_docker_error(error: &bollard::errors::Error) {
+    if !matches!(
+        error,
+        bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    ) {
+        DOCKER_API_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct MetricsState {
+    repl_sender: UnboundedSender<ReplCommand>,
+    hosts: Vec<Arc<Host>>,
+}
+
+/// Starts an HTTP server exposing cluster and container metrics in the Prometheus text format.
+pub fn spawn_metrics_server(
+    metrics_config: &MetricsConfig,
+    repl_sender: UnboundedSender<ReplCommand>,
+    hosts: Vec<Arc<Host>>,
+) -> Result<(), hyper::Error> {
+    let state = Arc::new(MetricsState { repl_sender, hosts });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, state.clone())
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&metrics_config.bind)?.serve(make_service);
+    info!("Serving metrics on {}", metrics_config.bind);
+    tokio::spawn(async move {
+        if let Err(why) = server.await {
+            error!("Metrics server stopped: {}", why);
+        }
+    });
+    Ok(())
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    state: Arc<MetricsState>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(text_response(
+            StatusCode::NOT_FOUND,
+            "Not found".to_string(),
+        ));
+    }
+
+    let (status_sender, status_receiver) = oneshot::channel();
+    let status = match state
+        .repl_sender
+        .send(ReplCommand::GetStatus(status_sender))
+    {
+        Ok(()) => status_receiver.await.ok(),
+        Err(_) => None,
+    };
+    let response = match status {
+        Some(status) => {
+            let container_stats = get_container_stats(&state.hosts, &status).await;
+            text_response(
+                StatusCode::OK,
+                format_metrics(&status, &container_stats, &state.hosts),
+            )
+        }
+        None => text_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "R2Wraith is shutting down".to_string(),
+        ),
+    };
+    Ok(response)
+}
+
+async fn get_container_stats<'a>(
+    hosts: &[Arc<Host>],
+    status: &'a [ServerStatus],
+) -> Vec<(&'a ServerStatus, Stats)> {
+    let stats_futures = status.iter().filter_map(|server_status| {
+        let container_id = server_status.container_id.as_ref()?;
+        let host_name = server_status.host.as_ref()?;
+        let runtime = &hosts.iter().find(|host| &host.name == host_name)?.runtime;
+        Some(async move {
+            let mut stats_stream = runtime.stats(container_id);
+            match stats_stream.next().await? {
+                Ok(stats) => Some((server_status, stats)),
+                Err(why) => {
+                    record_docker_error(&why);
+                    None
+                }
+            }
+        })
+    });
+    futures::future::join_all(stats_futures)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+fn format_metrics(
+    status: &[ServerStatus],
+    container_stats: &[(&ServerStatus, Stats)],
+    hosts: &[Arc<Host>],
+) -> String {
+    let mut metrics = MetricsWriter::default();
+
+    let running_count = status
+        .iter()
+        .filter(|server_status| is_running(server_status))
+        .count();
+    metrics.family(
+        "r2wraith_servers_running",
+        "gauge",
+        "Number of servers that are running.",
+    );
+    metrics.sample("r2wraith_servers_running", &[], running_count);
+
+    metrics.family(
+        "r2wraith_server_up",
+        "gauge",
+        "Whether the server is running (1) or not (0).",
+    );
+    for server_status in status {
+        metrics.sample(
+            "r2wraith_server_up",
+            &[("server", &server_status.id), ("name", &server_status.name)],
+            is_running(server_status) as u8,
+        );
+    }
+
+    metrics.family(
+        "r2wraith_server_ready",
+        "gauge",
+        "Whether the server has passed its ready check (1) or not (0).",
+    );
+    for server_status in status {
+        metrics.sample(
+            "r2wraith_server_ready",
+            &[("server", &server_status.id)],
+            (server_status.state == ServerStateKind::Running) as u8,
+        );
+    }
+
+    metrics.family(
+        "r2wraith_server_uptime_seconds",
+        "gauge",
+        "Seconds since the server's container was started.",
+    );
+    for server_status in status {
+        if let Some(uptime_seconds) = server_status.uptime_seconds {
+            metrics.sample(
+                "r2wraith_server_uptime_seconds",
+                &[("server", &server_status.id)],
+                uptime_seconds,
+            );
+        }
+    }
+
+    metrics.family(
+        "r2wraith_server_players",
+        "gauge",
+        "Number of players connected to the server, according to its logs.",
+    );
+    for server_status in status {
+        if let Some(players) = &server_status.players {
+            metrics.sample(
+                "r2wraith_server_players",
+                &[("server", &server_status.id)],
+                players.len(),
+            );
+        }
+    }
+
+    metrics.family(
+        "r2wraith_server_restarts_total",
+        "counter",
+        "Number of times the server has been restarted since R2Wraith started.",
+    );
+    for server_status in status {
+        let restart_counts = &server_status.restart_counts;
+        for (reason, count) in [
+            ("crash", restart_counts.crash),
+            ("schedule", restart_counts.schedule),
+            ("manual", restart_counts.manual),
+        ] {
+            metrics.sample(
+                "r2wraith_server_restarts_total",
+                &[("server", &server_status.id), ("reason", reason)],
+                count,
+            );
+        }
+    }
+
+    metrics.family(
+        "r2wraith_server_update_available",
+        "gauge",
+        "Whether a newer copy of the server's image has been pulled since it started (1) or not (0).",
+    );
+    for server_status in status {
+        if is_running(server_status) {
+            metrics.sample(
+                "r2wraith_server_update_available",
+                &[("server", &server_status.id)],
+                server_status.update_available as u8,
+            );
+        }
+    }
+
+    metrics.family(
+        "r2wraith_game_ports_used",
+        "gauge",
+        "Number of game ports in use by running servers on the host.",
+    );
+    for host in hosts {
+        let game_ports_used = status
+            .iter()
+            .filter(|server_status| server_status.host.as_ref() == Some(&host.name))
+            .count();
+        metrics.sample(
+            "r2wraith_game_ports_used",
+            &[("host", &host.name)],
+            game_ports_used,
+        );
+    }
+    metrics.family(
+        "r2wraith_game_ports_total",
+        "gauge",
+        "Number of game ports in the host's game-ports range.",
+    );
+    for host in hosts {
+        metrics.sample(
+            "r2wraith_game_ports_total",
+            &[("host", &host.name)],
+            host.limits.game_ports.clone().count(),
+        );
+    }
+    metrics.family(
+        "r2wraith_host_capacity",
+        "gauge",
+        "Most servers that can run on the host at once, for hosts with a capacity.",
+    );
+    for host in hosts {
+        if let Some(capacity) = host.limits.capacity {
+            metrics.sample("r2wraith_host_capacity", &[("host", &host.name)], capacity);
+        }
+    }
+
+    metrics.family(
+        "r2wraith_docker_api_errors_total",
+        "counter",
+        "Number of failed Docker API calls.",
+    );
+    metrics.sample(
+        "r2wraith_docker_api_errors_total",
+        &[],
+        DOCKER_API_ERRORS.load(Ordering::Relaxed),
+    );
+
+    metrics.family(
+        "r2wraith_container_cpu_seconds_total",
+        "counter",
+        "CPU time used by the server's container.",
+    );
+    for (server_status, stats) in container_stats {
+        metrics.sample(
+            "r2wraith_container_cpu_seconds_total",
+            &[("server", &server_status.id)],
+            stats.cpu_stats.cpu_usage.total_usage as f64 / 1_000_000_000.,
+        );
+    }
+
+    metrics.family(
+        "r2wraith_container_memory_bytes",
+        "gauge",
+        "Memory used by the server's container.",
+    );
+    for (server_status, stats) in container_stats {
+        if let Some(usage) = stats.memory_stats.usage {
+            metrics.sample(
+                "r2wraith_container_memory_bytes",
+                &[("server", &server_status.id)],
+                usage,
+            );
+        }
+    }
+
+    metrics.output
+}
+
+#[derive(Default)]
+struct MetricsWriter {
+    output: String,
+}
+
+impl MetricsWriter {
+    fn family(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<_> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+                .collect();
+            let _ = write!(self.output, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.output, " {}", value);
+    }
+}
+
+/// Servers count as running once their container has started, even if they aren't ready yet.
+fn is_running(server_status: &ServerStatus) -> bool {
+    matches!(
+        server_status.state,
+        ServerStateKind::Starting | ServerStateKind::Running
+    )
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn text_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .unwrap()
+}
@@ -0,0 +1,173 @@
+use crate::config::{NotificationConfig, NotificationEvent, NotificationFormat};
+use crate::server_cluster::Server;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub server: String,
+    pub name: String,
+    pub message: String,
+    pub time: DateTime<Utc>,
+}
+
+/// Hands lifecycle events off to a background task that posts them to the configured webhooks.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    sender: Option<UnboundedSender<Notification>>,
+}
+
+impl Notifier {
+    pub fn spawn(notification_configs: Vec<NotificationConfig>) -> Self {
+        if notification_configs.is_empty() {
+            return Notifier::default();
+        }
+
+        let (sender, mut receiver) = unbounded_channel::<Notification>();
+        tokio::spawn(async move {
+            let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+                Ok(client) => client,
+                Err(why) => {
+                    warn!("Failed to create webhook client: {}", why);
+                    return;
+                }
+            };
+            let mut webhooks: Vec<_> = notification_configs
+                .into_iter()
+                .map(|config| Webhook {
+                    config,
+                    rate_limits: HashMap::new(),
+                })
+                .collect();
+
+            while let Some(notification) = receiver.recv().await {
+                for webhook in &mut webhooks {
+                    webhook.send(&client, &notification).await;
+                }
+            }
+        });
+
+        Notifier {
+            sender: Some(sender),
+        }
+    }
+
+    pub fn notify(&self, event: NotificationEvent, server: &Server, message: String) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Notification {
+                event,
+                server: server.id.clone(),
+                name: server.config.name.clone(),
+                message,
+                time: Utc::now(),
+            });
+        }
+    }
+}
+
+struct Webhook {
+    config: NotificationConfig,
+    /// Kept for each server and event, so one noisy server can't use up the limit for the rest.
+    rate_limits: HashMap<(String, NotificationEvent), RateLimit>,
+}
+
+#[derive(Default)]
+struct RateLimit {
+    sent_times: VecDeque<DateTime<Utc>>,
+    suppressed_count: u32,
+}
+
+impl Webhook {
+    async fn send(&mut self, client: &reqwest::Client, notification: &Notification) {
+        if !self.config.events.contains(&notification.event) {
+            return;
+        }
+
+        // Drop anything over the rate limit, so a crash loop doesn't flood the channel
+        let window =
+            chrono::Duration::milliseconds((self.config.rate_limit_seconds * 1000.) as i64);
+        let rate_limit = self
+            .rate_limits
+            .entry((notification.server.clone(), notification.event))
+            .or_default();
+        while let Some(first_sent_time) = rate_limit.sent_times.front() {
+            if notification.time - *first_sent_time > window {
+                rate_limit.sent_times.pop_front();
+            } else {
+                break;
+            }
+        }
+        if rate_limit.sent_times.len() as u32 >= self.config.rate_limit_count {
+            debug!(
+                "Not sending notification to {}, it's over the rate limit",
+                self.config.url
+            );
+            rate_limit.suppressed_count += 1;
+            return;
+        }
+
+        let payload = match self.config.format {
+            NotificationFormat::Json => json!({
+                "event": notification.event,
+                "server": notification.server,
+                "name": notification.name,
+                "message": notification.message,
+                "time": notification.time,
+                "suppressed": rate_limit.suppressed_count,
+            }),
+            NotificationFormat::Discord => {
+                discord_payload(notification, rate_limit.suppressed_count)
+            }
+        };
+        rate_limit.sent_times.push_back(notification.time);
+        rate_limit.suppressed_count = 0;
+
+        let result = client
+            .post(&self.config.url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(why) = result {
+            warn!(
+                "Failed to send notification to {}: {}",
+                self.config.url, why
+            );
+        }
+    }
+}
+
+fn discord_payload(notification: &Notification, suppressed_count: u32) -> serde_json::Value {
+    let (title, color) = match notification.event {
+        NotificationEvent::Crash => ("Server crashed", 0xe67e22),
+        NotificationEvent::CrashLooping => ("Server is crash-looping", 0xe74c3c),
+        NotificationEvent::ScheduledRestart => ("Scheduled restart", 0x3498db),
+        NotificationEvent::StartFailed => ("Server failed to start", 0xe74c3c),
+        NotificationEvent::PortsExhausted => ("No free ports", 0xe74c3c),
+        NotificationEvent::RolloutFailed => ("Rollout aborted", 0xe74c3c),
+    };
+    let mut description = notification.message.clone();
+    if suppressed_count > 0 {
+        description.push_str(&format!(
+            "\n\n{} earlier notifications were not sent because of the rate limit",
+            suppressed_count
+        ));
+    }
+
+    json!({
+        "username": "R2Wraith",
+        "embeds": [{
+            "title": format!("{}: {}", title, notification.name),
+            "description": description,
+            "color": color,
+            "timestamp": notification.time,
+        }],
+    })
+}
@@ -0,0 +1,65 @@
+use log::debug;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+/// How long to wait for a reply to a UDP ready check, which is tried again on the next poll.
+const UDP_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends the payload to the server's game port, and returns true if anything comes back.
+pub async fn probe_udp(address: &str, game_port: u16, payload: &[u8]) -> bool {
+    let target = if address.contains(':') {
+        format!("[{}]:{}", address, game_port)
+    } else {
+        format!("{}:{}", address, game_port)
+    };
+    let bind_address = if address.contains(':') {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+
+    let probe = async {
+        let socket = UdpSocket::bind(bind_address).await?;
+        socket.connect(&target).await?;
+        socket.send(payload).await?;
+        let mut reply = [0; 1400];
+        socket.recv(&mut reply).await
+    };
+    match timeout(UDP_PROBE_TIMEOUT, probe).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(why)) => {
+            debug!("UDP ready check against {} failed: {}", target, why);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probe_gets_a_reply() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let game_port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut request = [0; 1400];
+            let (length, address) = server.recv_from(&mut request).await.unwrap();
+            server.send_to(&request[..length], address).await.unwrap();
+        });
+
+        assert!(probe_udp("127.0.0.1", game_port, b"\xff\xff\xff\xffping").await);
+    }
+
+    #[tokio::test]
+    async fn probe_without_a_reply_fails() {
+        // Kept open so the probe times out, rather than being refused
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let game_port = server.local_addr().unwrap().port();
+
+        assert!(!probe_udp("127.0.0.1", game_port, b"\xff\xff\xff\xffping").await);
+        drop(server);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::config::Config;
+use crate::hosts::HostLimits;
+use crate::server_cluster::{place_server, HostUsage, Placement, Server};
+use crate::{get_server_list_from_config, load_config};
+use serde_json::json;
+
+/// Prints the container spec each server would be started with, without connecting to Docker.
+pub fn run_render(args: Vec<String>) -> i32 {
+    let is_json = args.iter().any(|arg| arg == "--json");
+    let mut positional_args = args.iter().filter(|arg| *arg != "--json");
+    let config_file_path = positional_args
+        .next()
+        .map(String::as_str)
+        .unwrap_or("config.toml");
+    let server_id = positional_args.next();
+
+    let full_config_path = std::env::current_dir().unwrap().join(config_file_path);
+    let config = match load_config(&full_config_path) {
+        Ok(config) => config,
+        Err(why) => {
+            eprintln!("Failed to read config file: {}", why);
+            return 1;
+        }
+    };
+    let config_dir = full_config_path.parent().unwrap();
+    let servers = get_server_list_from_config(&config, config_dir);
+    let server_placements = place_servers(&config, &servers);
+
+    let mut rendered_servers = Vec::new();
+    let mut is_server_found = false;
+    let mut has_unplaced_servers = false;
+    for (server, placement) in servers.iter().zip(server_placements) {
+        if server_id.map(|id| *id != server.id).unwrap_or(false) {
+            continue;
+        }
+        is_server_found = true;
+
+        match placement {
+            Ok((host_name, placement)) => {
+                rendered_servers.push(render_server(
+                    server,
+                    &host_name,
+                    placement.game_port,
+                    placement.auth_port,
+                    &config.instance_id,
+                ));
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                has_unplaced_servers = true;
+            }
+        }
+    }
+    if let Some(server_id) = server_id {
+        if !is_server_found {
+            eprintln!("There is no server called {}", server_id);
+            return 1;
+        }
+    }
+
+    if is_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&rendered_servers).unwrap()
+        );
+    } else {
+        for rendered_server in &rendered_servers {
+            print_server(rendered_server);
+        }
+    }
+
+    // The servers that could be placed are still shown, but this is an error like in `check`
+    if has_unplaced_servers {
+        1
+    } else {
+        0
+    }
+}
+
+/// Picks hosts and ports the same way as when the servers are first started, with none already
+/// running.
+fn place_servers(config: &Config, servers: &[Server]) -> Vec<Result<(String, Placement), String>> {
+    let host_configs = config.host_configs();
+    let host_limits: Vec<_> = host_configs
+        .iter()
+        .map(|host_config| HostLimits::new(host_config, config))
+        .collect();
+    let hosts: Vec<_> = host_configs
+        .iter()
+        .zip(&host_limits)
+        .map(|(host_config, host_limits)| (host_config.name.as_str(), host_limits))
+        .collect();
+    let mut host_usages: Vec<_> = hosts.iter().map(|_| HostUsage::default()).collect();
+    servers
+        .iter()
+        .map(|server| {
+            let placement =
+                place_server(server, &hosts, &host_usages).map_err(|why| why.to_string())?;
+            host_usages[placement.host_index].add(placement.game_port, placement.auth_port);
+            Ok((hosts[placement.host_index].0.to_string(), placement))
+        })
+        .collect()
+}
+
+fn render_server(
+    server: &Server,
+    host_name: &str,
+    game_port: u16,
+    auth_port: Option<u16>,
+    instance_id: &str,
+) -> serde_json::Value {
+    let built_args = server.build_args(game_port, auth_port);
+    json!({
+        "id": server.id,
+        "name": server.config.name,
+        "host": host_name,
+        "game_port": game_port,
+        "auth_port": auth_port,
+        "extra_arguments": built_args.extra_arg_tokens(),
+        "playlist_overrides": built_args.playlist_vars,
+        "container": server.build_container_config(game_port, auth_port, instance_id),
+    })
+}
+
+fn print_server(rendered_server: &serde_json::Value) {
+    let container = &rendered_server["container"];
+    let host_config = &container["HostConfig"];
+
+    println!(
+        "Server {} ({})",
+        rendered_server["id"].as_str().unwrap_or_default(),
+        rendered_server["name"].as_str().unwrap_or_default()
+    );
+    println!(
+        "  Host: {}",
+        rendered_server["host"].as_str().unwrap_or_default()
+    );
+    println!(
+        "  Image: {}",
+        container["Image"].as_str().unwrap_or_default()
+    );
+
+    println!("  Environment variables:");
+    for env_var in as_str_list(&container["Env"]) {
+        // The extra arguments are shown split up below
+        if !env_var.starts_with("NS_EXTRA_ARGUMENTS=") {
+            println!("    {}", env_var);
+        }
+    }
+
+    println!("  NS_EXTRA_ARGUMENTS:");
+    for extra_arg in as_str_list(&rendered_server["extra_arguments"]) {
+        println!("    {:?}", extra_arg);
+    }
+
+    println!("  Playlist overrides:");
+    if let Some(playlist_overrides) = rendered_server["playlist_overrides"].as_object() {
+        for (key, value) in playlist_overrides {
+            println!("    {} = {}", key, value.as_str().unwrap_or_default());
+        }
+    }
+
+    println!("  Binds:");
+    for bind in as_str_list(&host_config["Binds"]) {
+        println!("    {}", bind);
+    }
+
+    println!("  Ports:");
+    if let Some(port_bindings) = host_config["PortBindings"].as_object() {
+        for (container_port, host_bindings) in port_bindings {
+            let host_ports: Vec<_> = host_bindings
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|host_binding| host_binding["HostPort"].as_str())
+                .collect();
+            println!("    {} -> {}", container_port, host_ports.join(", "));
+        }
+    }
+
+    println!("  Resource limits:");
+    for (name, key) in [
+        ("Memory", "Memory"),
+        ("Memory + swap", "MemorySwap"),
+        ("CPU period", "CpuPeriod"),
+        ("CPU quota", "CpuQuota"),
+        ("CPU set", "CpusetCpus"),
+    ] {
+        let value = &host_config[key];
+        if !value.is_null() {
+            println!("    {}: {}", name, value);
+        }
+    }
+    println!();
+}
+
+fn as_str_list(value: &serde_json::Value) -> impl Iterator<Item = &str> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.as_str())
+}
@@ -0,0 +1,75 @@
+use crate::config::FilledGameConfig;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::VecDeque;
+
+pub enum FailureOutcome {
+    RetryAfter(Duration),
+    CrashLooping,
+}
+
+#[derive(Debug, Default)]
+pub struct RestartBackoff {
+    last_start_time: Option<DateTime<Utc>>,
+    failure_times: VecDeque<DateTime<Utc>>,
+    next_start_time: Option<DateTime<Utc>>,
+}
+
+impl RestartBackoff {
+    pub fn record_start(&mut self, time: DateTime<Utc>) {
+        self.last_start_time = Some(time);
+        self.next_start_time = None;
+    }
+
+    pub fn record_failure(
+        &mut self,
+        time: DateTime<Utc>,
+        game_config: &FilledGameConfig,
+    ) -> FailureOutcome {
+        let window = seconds_to_duration(game_config.restart_backoff_window_seconds);
+
+        // A server that stayed up for a whole window isn't crash-looping, so start counting again
+        if let Some(last_start_time) = self.last_start_time {
+            if time - last_start_time > window {
+                self.failure_times.clear();
+            }
+        }
+
+        self.failure_times.push_back(time);
+        while let Some(first_failure_time) = self.failure_times.front() {
+            if time - *first_failure_time > window {
+                self.failure_times.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let failure_count = self.failure_times.len() as u32;
+        if failure_count >= game_config.restart_backoff_failure_limit {
+            self.next_start_time = None;
+            return FailureOutcome::CrashLooping;
+        }
+
+        let delay_seconds = (game_config.restart_backoff_initial_seconds
+            * 2f64.powi(failure_count as i32 - 1))
+        .min(game_config.restart_backoff_max_seconds);
+        let delay = seconds_to_duration(delay_seconds);
+        self.next_start_time = Some(time + delay);
+        FailureOutcome::RetryAfter(delay)
+    }
+
+    pub fn can_start(&self, time: DateTime<Utc>) -> bool {
+        match self.next_start_time {
+            Some(next_start_time) => time >= next_start_time,
+            None => true,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.failure_times.clear();
+        self.next_start_time = None;
+    }
+}
+
+pub fn seconds_to_duration(seconds: f64) -> Duration {
+    Duration::milliseconds((seconds * 1000.) as i64)
+}
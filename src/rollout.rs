@@ -0,0 +1,173 @@
+use crate::config::NotificationEvent;
+use crate::notifications::Notifier;
+use crate::server_cluster::{RestartReason, Server, ServerState};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Restarts servers a few at a time, waiting for each one to be ready again before restarting
+/// more, so there are always servers up. Stops at the first server that doesn't come back.
+#[derive(Debug)]
+pub struct Rollout {
+    pending_server_ids: VecDeque<String>,
+    restarting_servers: Vec<RestartingServer>,
+    concurrency: usize,
+    ready_timeout: chrono::Duration,
+    restarted_count: usize,
+}
+
+#[derive(Debug)]
+struct RestartingServer {
+    server_id: String,
+    restart_time: DateTime<Utc>,
+    /// The container the server had before it was restarted, so a stop that failed can be spotted.
+    container_id: String,
+    /// The server's failure count before it was restarted, so new failures can be spotted.
+    failure_count: u32,
+}
+
+impl Rollout {
+    pub fn new(
+        server_ids: Vec<String>,
+        concurrency: usize,
+        ready_timeout: chrono::Duration,
+    ) -> Self {
+        Rollout {
+            pending_server_ids: server_ids.into(),
+            restarting_servers: Vec::new(),
+            concurrency,
+            ready_timeout,
+            restarted_count: 0,
+        }
+    }
+
+    /// Checks on the servers that are restarting, and restarts more once they're ready. Returns
+    /// false once the rollout has finished or been aborted.
+    pub fn poll(
+        &mut self,
+        servers: &mut [Server],
+        poll_time: DateTime<Utc>,
+        notifier: &Notifier,
+        stop_notify: &Arc<Notify>,
+    ) -> bool {
+        let restarting_servers = std::mem::take(&mut self.restarting_servers);
+        for restarting_server in restarting_servers {
+            let server = match servers
+                .iter()
+                .find(|server| server.id == restarting_server.server_id)
+            {
+                Some(server) => server,
+                None => {
+                    warn!(
+                        "Server {} was removed from the config during the rollout",
+                        restarting_server.server_id
+                    );
+                    continue;
+                }
+            };
+
+            let failure = if server.container_id() == Some(&restarting_server.container_id) {
+                Some(format!("server {} couldn't be stopped", server.id))
+            } else if server.failure_count() > restarting_server.failure_count {
+                Some(format!("server {} failed to start", server.id))
+            } else if let ServerState::Stopped | ServerState::CrashLooping = server.state {
+                Some(format!("server {} was stopped", server.id))
+            } else if server.is_ready() {
+                None
+            } else if poll_time - restarting_server.restart_time > self.ready_timeout {
+                Some(format!(
+                    "server {} wasn't ready within {} seconds",
+                    server.id,
+                    self.ready_timeout.num_seconds()
+                ))
+            } else {
+                self.restarting_servers.push(restarting_server);
+                continue;
+            };
+
+            match failure {
+                Some(failure) => {
+                    self.abort(server, failure, notifier);
+                    return false;
+                }
+                None => {
+                    self.restarted_count += 1;
+                    info!(
+                        "Server {} is ready, {} server(s) left to roll out",
+                        server.id,
+                        self.pending_server_ids.len() + self.restarting_servers.len()
+                    );
+                }
+            }
+        }
+
+        let mut batch = Vec::new();
+        while self.restarting_servers.len() + batch.len() < self.concurrency {
+            let server_id = match self.pending_server_ids.pop_front() {
+                Some(server_id) => server_id,
+                None => break,
+            };
+            let server = match servers.iter().find(|server| server.id == server_id) {
+                Some(server) => server,
+                None => {
+                    warn!(
+                        "Server {} was removed from the config during the rollout",
+                        server_id
+                    );
+                    continue;
+                }
+            };
+            let container_id = match server.container_id() {
+                Some(container_id) => container_id.to_string(),
+                None => {
+                    info!("Server {} isn't running, skipping it", server.id);
+                    continue;
+                }
+            };
+
+            info!("Rolling out {}", server.id);
+            batch.push(RestartingServer {
+                server_id,
+                restart_time: poll_time,
+                container_id,
+                failure_count: server.failure_count(),
+            });
+        }
+
+        // The servers are stopped in the background, and checked on by the next poll
+        for server in servers
+            .iter_mut()
+            .filter(|server| is_in_batch(&batch, server))
+        {
+            server.restart(RestartReason::Manual, stop_notify);
+        }
+        self.restarting_servers.extend(batch);
+
+        if self.restarting_servers.is_empty() {
+            info!(
+                "Rollout finished, restarted {} server(s)",
+                self.restarted_count
+            );
+            return false;
+        }
+        true
+    }
+
+    fn abort(&self, server: &Server, failure: String, notifier: &Notifier) {
+        let message = format!(
+            "Rollout aborted because {}, {} server(s) weren't restarted",
+            failure,
+            self.pending_server_ids.len()
+        );
+        error!("{}", message);
+        notifier.notify(NotificationEvent::RolloutFailed, server, message);
+    }
+}
+
+fn is_in_batch(batch: &[RestartingServer], server: &Server) -> bool {
+    batch
+        .iter()
+        .any(|restarting_server| restarting_server.server_id == server.id)
+}
@@ -0,0 +1,495 @@
+use crate::config::{DockerConfig, RuntimeConfig, RuntimeEngine};
+use crate::metrics::record_docker_error;
+use crate::runtime::podman::PodmanRuntime;
+use async_trait::async_trait;
+use bollard::container::{
+    AttachContainerOptions, AttachContainerResults, Config, CreateContainerOptions,
+    KillContainerOptions, ListContainersOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+    Stats, StatsOptions, StopContainerOptions,
+};
+use bollard::errors::Error;
+use bollard::image::CreateImageOptions;
+use bollard::models::{
+    ContainerCreateResponse, ContainerInspectResponse, ContainerSummary, CreateImageInfo,
+    EventMessage, ImageInspect,
+};
+use bollard::system::EventsOptions;
+use bollard::system::Version;
+use bollard::{Docker, API_DEFAULT_VERSION};
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+#[cfg(test)]
+pub mod fake;
+mod podman;
+
+/// Everything R2Wraith needs from the container engine, so servers aren't tied to a Docker
+/// client. Errors use the Docker API's, since that's what every runtime speaks.
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    /// The current time, which container created times are compared against.
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn create_container(
+        &self,
+        name: &str,
+        config: Config<String>,
+    ) -> Result<ContainerCreateResponse, Error>;
+
+    async fn start_container(&self, container_id: &str) -> Result<(), Error>;
+
+    /// Stops the container, letting the runtime kill it if it doesn't exit within the timeout.
+    async fn stop_container(&self, container_id: &str, timeout: Duration) -> Result<(), Error>;
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), Error>;
+
+    /// Removes the container, even if it's still running.
+    async fn remove_container(&self, container_id: &str) -> Result<(), Error>;
+
+    async fn inspect_container(
+        &self,
+        container_id: &str,
+    ) -> Result<ContainerInspectResponse, Error>;
+
+    /// Lists the running containers that have a label in `key=value` form.
+    async fn list_containers(&self, label: &str) -> Result<Vec<ContainerSummary>, Error>;
+
+    /// Attaches to the container's stdin and output, without any earlier output.
+    async fn attach_container(&self, container_id: &str) -> Result<AttachContainerResults, Error>;
+
+    /// Follows the container's stdout and stderr from when it was started.
+    fn logs(&self, container_id: &str) -> BoxStream<'static, Result<LogOutput, Error>>;
+
+    /// Gets a single resource usage sample for the container.
+    fn stats(&self, container_id: &str) -> BoxStream<'static, Result<Stats, Error>>;
+
+    /// Follows container lifecycle events that might mean a server has stopped.
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>>;
+
+    /// Inspects an image that's on the host, failing with a 404 if it hasn't been pulled.
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error>;
+
+    /// Pulls an image from its registry, following the progress of each layer.
+    fn pull_image(&self, image: &str) -> BoxStream<'static, Result<CreateImageInfo, Error>>;
+}
+
+#[async_trait]
+impl ContainerRuntime for Docker {
+    async fn create_container(
+        &self,
+        name: &str,
+        config: Config<String>,
+    ) -> Result<ContainerCreateResponse, Error> {
+        Docker::create_container(self, Some(CreateContainerOptions { name }), config).await
+    }
+
+    async fn start_container(&self, container_id: &str) -> Result<(), Error> {
+        Docker::start_container::<String>(self, container_id, None).await
+    }
+
+    async fn stop_container(&self, container_id: &str, timeout: Duration) -> Result<(), Error> {
+        Docker::stop_container(
+            self,
+            container_id,
+            Some(StopContainerOptions {
+                t: timeout.as_secs() as i64,
+            }),
+        )
+        .await
+    }
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), Error> {
+        Docker::kill_container(self, container_id, None::<KillContainerOptions<String>>).await
+    }
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), Error> {
+        Docker::remove_container(
+            self,
+            container_id,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn inspect_container(
+        &self,
+        container_id: &str,
+    ) -> Result<ContainerInspectResponse, Error> {
+        Docker::inspect_container(self, container_id, None).await
+    }
+
+    async fn list_containers(&self, label: &str) -> Result<Vec<ContainerSummary>, Error> {
+        let filters = [("label", vec![label])].into_iter().collect();
+        Docker::list_containers(
+            self,
+            Some(ListContainersOptions {
+                all: false,
+                filters,
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn attach_container(&self, container_id: &str) -> Result<AttachContainerResults, Error> {
+        Docker::attach_container::<String>(
+            self,
+            container_id,
+            Some(AttachContainerOptions {
+                stdin: Some(true),
+                stdout: Some(true),
+                stderr: Some(true),
+                stream: Some(true),
+                logs: Some(false),
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    fn logs(&self, container_id: &str) -> BoxStream<'static, Result<LogOutput, Error>> {
+        Docker::logs::<String>(
+            self,
+            container_id,
+            Some(LogsOptions {
+                follow: true,
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }),
+        )
+        .boxed()
+    }
+
+    fn stats(&self, container_id: &str) -> BoxStream<'static, Result<Stats, Error>> {
+        Docker::stats(
+            self,
+            container_id,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: true,
+            }),
+        )
+        .boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>> {
+        let filters: HashMap<_, _> = [("type", vec!["container"]), ("event", vec!["die", "stop"])]
+            .into_iter()
+            .collect();
+        Docker::events(
+            self,
+            Some(EventsOptions {
+                filters,
+                ..Default::default()
+            }),
+        )
+        .boxed()
+    }
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error> {
+        Docker::inspect_image(self, image).await
+    }
+
+    fn pull_image(&self, image: &str) -> BoxStream<'static, Result<CreateImageInfo, Error>> {
+        Docker::create_image(
+            self,
+            Some(CreateImageOptions {
+                from_image: image.to_string(),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .boxed()
+    }
+}
+
+/// A connection to the container engine, along with what was found out about it.
+pub struct ConnectedRuntime {
+    pub runtime: Arc<dyn ContainerRuntime>,
+    pub description: String,
+    /// The lowest port that can be bound, if the runtime is rootless.
+    pub unprivileged_port_start: Option<u16>,
+}
+
+/// Where the container engine's API can be reached.
+pub enum Endpoint {
+    Socket(PathBuf),
+    Tcp { address: String, is_https: bool },
+}
+
+/// The files needed to connect over TLS, which verifies the engine against the CA certificate and
+/// identifies us with the client certificate.
+pub struct TlsFiles {
+    pub ca_cert: PathBuf,
+    pub client_cert: PathBuf,
+    pub client_key: PathBuf,
+}
+
+pub fn parse_docker_host(host: &str) -> Result<Endpoint, String> {
+    match host.split_once("://") {
+        Some(("unix", path)) | Some(("npipe", path)) => Ok(Endpoint::Socket(PathBuf::from(path))),
+        Some(("tcp", address)) | Some(("http", address)) => Ok(Endpoint::Tcp {
+            address: address.to_string(),
+            is_https: false,
+        }),
+        Some(("https", address)) => Ok(Endpoint::Tcp {
+            address: address.to_string(),
+            is_https: true,
+        }),
+        Some(("ssh", _)) => Err(format!(
+            "host {} uses ssh://, which isn't supported. Forward the Docker socket with `ssh -L` and use a unix:// host instead",
+            host
+        )),
+        _ => Err(format!(
+            "host {} should start with unix://, tcp:// or https://",
+            host
+        )),
+    }
+}
+
+/// Finds the TLS files from `cert-dir` and the individual file options, or None if TLS isn't
+/// configured. Relative paths are from the config directory.
+pub fn find_tls_files(
+    docker_config: &DockerConfig,
+    config_dir: &Path,
+) -> Result<Option<TlsFiles>, String> {
+    if docker_config.cert_dir.is_none()
+        && docker_config.ca_cert.is_none()
+        && docker_config.client_cert.is_none()
+        && docker_config.client_key.is_none()
+    {
+        return Ok(None);
+    }
+
+    let find_file = |path: &Option<String>, key: &str, default_name: &str| match (
+        path,
+        &docker_config.cert_dir,
+    ) {
+        (Some(path), _) => Ok(config_dir.join(path)),
+        (None, Some(cert_dir)) => Ok(config_dir.join(cert_dir).join(default_name)),
+        (None, None) => Err(format!(
+            "TLS needs {} to be set, or a cert-dir containing {}",
+            key, default_name
+        )),
+    };
+    let tls_files = TlsFiles {
+        ca_cert: find_file(&docker_config.ca_cert, "ca-cert", "ca.pem")?,
+        client_cert: find_file(&docker_config.client_cert, "client-cert", "cert.pem")?,
+        client_key: find_file(&docker_config.client_key, "client-key", "key.pem")?,
+    };
+    for path in [
+        &tls_files.ca_cert,
+        &tls_files.client_cert,
+        &tls_files.client_key,
+    ] {
+        if !path.is_file() {
+            return Err(format!("TLS file {} doesn't exist", path.display()));
+        }
+    }
+    Ok(Some(tls_files))
+}
+
+/// Connects to the configured container engine, finding its socket and whether it's Docker or
+/// Podman if they weren't given.
+pub async fn connect(
+    runtime_config: &RuntimeConfig,
+    docker_config: &DockerConfig,
+    config_dir: &Path,
+) -> Result<ConnectedRuntime, Box<dyn StdError>> {
+    let request_timeout = docker_config.request_timeout_seconds.ceil().max(1.) as u64;
+    let (docker, endpoint_name, is_local) = match (&docker_config.host, &runtime_config.socket) {
+        (Some(_), Some(_)) => {
+            return Err("only one of docker.host and runtime.socket can be set".into());
+        }
+        (Some(host), None) => {
+            let docker = connect_to_host(host, docker_config, config_dir, request_timeout)?;
+            let is_local = matches!(parse_docker_host(host), Ok(Endpoint::Socket(_)));
+            (docker, host.clone(), is_local)
+        }
+        (None, _) => match find_socket(runtime_config) {
+            Some(socket) => {
+                let docker = Docker::connect_with_socket(
+                    &socket.to_string_lossy(),
+                    request_timeout,
+                    API_DEFAULT_VERSION,
+                )?;
+                (docker, socket.display().to_string(), true)
+            }
+            None => {
+                let docker = Docker::connect_with_local_defaults()?
+                    .with_timeout(Duration::from_secs(request_timeout));
+                let endpoint_name = std::env::var("DOCKER_HOST")
+                    .unwrap_or_else(|_| "the default Docker socket".to_string());
+                (docker, endpoint_name, true)
+            }
+        },
+    };
+    debug!("Connecting to {}", endpoint_name);
+
+    // Requests are lazy, so this is the first time the connection is actually made
+    let connect_timeout = Duration::try_from_secs_f64(docker_config.connect_timeout_seconds)
+        .map_err(|_| "docker.connect-timeout-seconds must be more than 0")?;
+    let version = match timeout(connect_timeout, Docker::version(&docker)).await {
+        Ok(version) => version?,
+        Err(_) => {
+            return Err(format!(
+                "no response from {} after {} seconds",
+                endpoint_name,
+                connect_timeout.as_secs_f64()
+            )
+            .into())
+        }
+    };
+    let is_podman = match runtime_config.engine {
+        RuntimeEngine::Auto => is_podman_version(&version),
+        RuntimeEngine::Docker => false,
+        RuntimeEngine::Podman => true,
+    };
+
+    // This is only needed to warn about ports that can't be used, so a failure isn't fatal
+    let is_rootless = match timeout(connect_timeout, docker.info()).await {
+        Ok(Ok(info)) => info
+            .security_options
+            .iter()
+            .flatten()
+            .any(|security_option| security_option.contains("rootless")),
+        Ok(Err(why)) => {
+            record_docker_error(&why);
+            warn!("Failed to get container runtime info: {}", why);
+            false
+        }
+        Err(_) => {
+            warn!("Timed out getting container runtime info");
+            false
+        }
+    };
+    // The sysctl can only be read for a runtime on this machine
+    let unprivileged_port_start = match (is_rootless, is_local) {
+        (false, _) => None,
+        (true, true) => Some(
+            std::fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+                .ok()
+                .and_then(|contents| contents.trim().parse().ok())
+                .unwrap_or(1024),
+        ),
+        (true, false) => Some(1024),
+    };
+
+    let description = format!(
+        "{} {}{} at {}",
+        if is_podman { "Podman" } else { "Docker" },
+        version.version.as_deref().unwrap_or("(unknown version)"),
+        if is_rootless { " (rootless)" } else { "" },
+        endpoint_name
+    );
+    let runtime: Arc<dyn ContainerRuntime> = if is_podman {
+        Arc::new(PodmanRuntime::new(docker))
+    } else {
+        Arc::new(docker)
+    };
+    Ok(ConnectedRuntime {
+        runtime,
+        description,
+        unprivileged_port_start,
+    })
+}
+
+fn connect_to_host(
+    host: &str,
+    docker_config: &DockerConfig,
+    config_dir: &Path,
+    request_timeout: u64,
+) -> Result<Docker, Box<dyn StdError>> {
+    let tls_files = find_tls_files(docker_config, config_dir)?;
+    let docker = match (parse_docker_host(host)?, tls_files) {
+        (Endpoint::Socket(path), tls_files) => {
+            if tls_files.is_some() {
+                warn!("Docker host {} is a socket, so TLS won't be used", host);
+            }
+            Docker::connect_with_socket(
+                &path.to_string_lossy(),
+                request_timeout,
+                API_DEFAULT_VERSION,
+            )?
+        }
+        (Endpoint::Tcp { address, .. }, Some(tls_files)) => Docker::connect_with_ssl(
+            &address,
+            &tls_files.client_key,
+            &tls_files.client_cert,
+            &tls_files.ca_cert,
+            request_timeout,
+            API_DEFAULT_VERSION,
+        )?,
+        (Endpoint::Tcp { is_https: true, .. }, None) => {
+            return Err(format!(
+                "host {} uses https://, so needs cert-dir or ca-cert, client-cert and client-key to be set",
+                host
+            )
+            .into());
+        }
+        (Endpoint::Tcp { address, .. }, None) => {
+            warn!(
+                "Connecting to Docker host {} without TLS, anyone who can reach it can control it",
+                host
+            );
+            Docker::connect_with_http(&address, request_timeout, API_DEFAULT_VERSION)?
+        }
+    };
+    Ok(docker)
+}
+
+/// Picks the API socket to connect to, or None to leave it to `DOCKER_HOST` or Docker's default.
+fn find_socket(runtime_config: &RuntimeConfig) -> Option<PathBuf> {
+    if let Some(socket) = &runtime_config.socket {
+        return Some(PathBuf::from(socket.trim_start_matches("unix://")));
+    }
+    if std::env::var_os("DOCKER_HOST").is_some() {
+        return None;
+    }
+
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    let docker_sockets = [
+        Some(PathBuf::from("/var/run/docker.sock")),
+        runtime_dir.as_ref().map(|dir| dir.join("docker.sock")),
+    ];
+    let podman_sockets = [
+        runtime_dir
+            .as_ref()
+            .map(|dir| dir.join("podman/podman.sock")),
+        Some(PathBuf::from("/run/podman/podman.sock")),
+    ];
+    let candidates: Vec<_> = match runtime_config.engine {
+        RuntimeEngine::Auto => docker_sockets.into_iter().chain(podman_sockets).collect(),
+        RuntimeEngine::Docker => docker_sockets.into_iter().collect(),
+        RuntimeEngine::Podman => podman_sockets.into_iter().collect(),
+    };
+    candidates
+        .into_iter()
+        .flatten()
+        .find(|socket| Path::new(socket).exists())
+}
+
+/// Podman's Docker-compatible API reports itself as a component, even when it's behind the
+/// Docker socket path.
+fn is_podman_version(version: &Version) -> bool {
+    version
+        .components
+        .iter()
+        .flatten()
+        .any(|component| component.name.contains("Podman"))
+}
@@ -0,0 +1,481 @@
+//! An in-memory container runtime for tests. Its clock follows tokio's, so tests that pause time
+//! can move both the restart schedule and any stop timeouts forward together.
+
+use super::ContainerRuntime;
+use crate::images::with_default_tag;
+use async_trait::async_trait;
+use bollard::container::{AttachContainerResults, Config, LogOutput, Stats};
+use bollard::errors::Error;
+use bollard::models::{
+    ContainerConfig, ContainerCreateResponse, ContainerInspectResponse, ContainerState,
+    ContainerSummary, CreateImageInfo, EventActor, EventMessage, EventMessageTypeEnum,
+    ImageInspect,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::time::{sleep, Instant};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+/// How a container responds to being stopped.
+#[derive(Debug, Clone, Copy)]
+pub enum StopBehaviour {
+    /// Exits straight away.
+    Normal,
+    /// Takes this long to exit, or is killed by the runtime at the stop timeout.
+    Slow(Duration),
+    /// Ignores stop and kill requests, and only goes away when it's forcibly removed.
+    Wedged,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeContainer {
+    pub id: String,
+    pub name: String,
+    pub config: Config<String>,
+    pub image_id: String,
+    pub created: DateTime<Utc>,
+    pub is_running: bool,
+    pub console_lines: Vec<String>,
+}
+
+struct ContainerEntry {
+    container: FakeContainer,
+    log_senders: Vec<UnboundedSender<Result<LogOutput, Error>>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    containers: BTreeMap<String, ContainerEntry>,
+    next_container_index: u32,
+    stop_behaviours: HashMap<String, StopBehaviour>,
+    event_senders: Vec<UnboundedSender<Result<EventMessage, Error>>>,
+    /// Image IDs by tagged name, for images that have been pulled.
+    images: HashMap<String, String>,
+    /// Image IDs by tagged name, for what a pull would get.
+    registry: HashMap<String, String>,
+    pulled_images: Vec<String>,
+}
+
+pub struct FakeRuntime {
+    start_time: DateTime<Utc>,
+    start_instant: Instant,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeRuntime {
+    pub fn new(start_time: DateTime<Utc>) -> Self {
+        FakeRuntime {
+            start_time,
+            start_instant: Instant::now(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Sets how containers with this name respond to being stopped, including ones created later.
+    pub fn set_stop_behaviour(&self, name: &str, stop_behaviour: StopBehaviour) {
+        self.lock()
+            .stop_behaviours
+            .insert(name.to_string(), stop_behaviour);
+    }
+
+    /// Every container that hasn't been removed, ordered by creation.
+    pub fn containers(&self) -> Vec<FakeContainer> {
+        let mut containers: Vec<_> = self
+            .lock()
+            .containers
+            .values()
+            .map(|entry| entry.container.clone())
+            .collect();
+        containers.sort_by(|a, b| a.id.cmp(&b.id));
+        containers
+    }
+
+    pub fn running_container(&self, name: &str) -> Option<FakeContainer> {
+        self.containers()
+            .into_iter()
+            .find(|container| container.name == name && container.is_running)
+    }
+
+    /// Writes a line to the container's output, as if the game had logged it.
+    pub fn push_log(&self, container_id: &str, line: &str) {
+        if let Some(entry) = self.lock().containers.get_mut(container_id) {
+            entry.log_senders.retain(|sender| {
+                sender
+                    .send(Ok(LogOutput::StdOut {
+                        message: format!("{}\n", line).into(),
+                    }))
+                    .is_ok()
+            });
+        }
+    }
+
+    /// Pushes an image to the registry, so the next pull of it gets this ID.
+    pub fn publish_image(&self, image: &str, image_id: &str) {
+        self.lock()
+            .registry
+            .insert(with_default_tag(image), image_id.to_string());
+    }
+
+    /// Puts an image on the host, as if it had been pulled before.
+    pub fn load_image(&self, image: &str, image_id: &str) {
+        self.lock()
+            .images
+            .insert(with_default_tag(image), image_id.to_string());
+    }
+
+    /// Every image that's been pulled, in order.
+    pub fn pulled_images(&self) -> Vec<String> {
+        self.lock().pulled_images.clone()
+    }
+
+    /// Makes the container exit on its own, as if the game had crashed.
+    pub fn crash(&self, container_id: &str) {
+        exit_container(&mut self.lock(), container_id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+
+    fn stop_behaviour(&self, container_id: &str) -> Result<StopBehaviour, Error> {
+        let state = self.lock();
+        let entry = state
+            .containers
+            .get(container_id)
+            .ok_or_else(|| not_found_error(container_id))?;
+        Ok(state
+            .stop_behaviours
+            .get(&entry.container.name)
+            .copied()
+            .unwrap_or(StopBehaviour::Normal))
+    }
+}
+
+/// Stops the container's process, removing the container too if it was created with auto-remove.
+fn exit_container(state: &mut FakeState, container_id: &str) {
+    let entry = match state.containers.get_mut(container_id) {
+        Some(entry) if entry.container.is_running => entry,
+        _ => return,
+    };
+    entry.container.is_running = false;
+    entry.log_senders.clear();
+    let name = entry.container.name.clone();
+    let is_auto_remove = entry
+        .container
+        .config
+        .host_config
+        .as_ref()
+        .and_then(|host_config| host_config.auto_remove)
+        .unwrap_or(false);
+    if is_auto_remove {
+        state.containers.remove(container_id);
+    }
+
+    let event = EventMessage {
+        typ: Some(EventMessageTypeEnum::CONTAINER),
+        action: Some("die".to_string()),
+        actor: Some(EventActor {
+            id: Some(container_id.to_string()),
+            attributes: Some([("name".to_string(), name)].into_iter().collect()),
+        }),
+        ..Default::default()
+    };
+    state
+        .event_senders
+        .retain(|sender| sender.send(Ok(event.clone())).is_ok());
+}
+
+fn not_found_error(container_id: &str) -> Error {
+    Error::DockerResponseServerError {
+        status_code: 404,
+        message: format!("No such container: {}", container_id),
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    fn now(&self) -> DateTime<Utc> {
+        self.start_time + chrono::Duration::from_std(self.start_instant.elapsed()).unwrap()
+    }
+
+    async fn create_container(
+        &self,
+        name: &str,
+        config: Config<String>,
+    ) -> Result<ContainerCreateResponse, Error> {
+        // Docker only has whole seconds for the created time in container lists
+        let created = Utc.timestamp_opt(self.now().timestamp(), 0).unwrap();
+        let mut state = self.lock();
+        let image = with_default_tag(config.image.as_deref().unwrap_or_default());
+        let image_id = match state.images.get(&image) {
+            Some(image_id) => image_id.clone(),
+            None => {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 404,
+                    message: format!("No such image: {}", image),
+                })
+            }
+        };
+        if state
+            .containers
+            .values()
+            .any(|entry| entry.container.name == name)
+        {
+            return Err(Error::DockerResponseServerError {
+                status_code: 409,
+                message: format!("The container name \"/{}\" is already in use", name),
+            });
+        }
+
+        state.next_container_index += 1;
+        let id = format!("{:064x}", state.next_container_index);
+        state.containers.insert(
+            id.clone(),
+            ContainerEntry {
+                container: FakeContainer {
+                    id: id.clone(),
+                    name: name.to_string(),
+                    config,
+                    image_id,
+                    created,
+                    is_running: false,
+                    console_lines: Vec::new(),
+                },
+                log_senders: Vec::new(),
+            },
+        );
+        Ok(ContainerCreateResponse {
+            id,
+            warnings: Vec::new(),
+        })
+    }
+
+    async fn start_container(&self, container_id: &str) -> Result<(), Error> {
+        let mut state = self.lock();
+        let entry = state
+            .containers
+            .get_mut(container_id)
+            .ok_or_else(|| not_found_error(container_id))?;
+        entry.container.is_running = true;
+        Ok(())
+    }
+
+    async fn stop_container(&self, container_id: &str, timeout: Duration) -> Result<(), Error> {
+        match self.stop_behaviour(container_id)? {
+            StopBehaviour::Normal => {}
+            StopBehaviour::Slow(delay) => sleep(delay.min(timeout)).await,
+            StopBehaviour::Wedged => futures::future::pending().await,
+        }
+        exit_container(&mut self.lock(), container_id);
+        Ok(())
+    }
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), Error> {
+        if let StopBehaviour::Wedged = self.stop_behaviour(container_id)? {
+            return Ok(());
+        }
+        exit_container(&mut self.lock(), container_id);
+        Ok(())
+    }
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), Error> {
+        let mut state = self.lock();
+        if !state.containers.contains_key(container_id) {
+            return Err(not_found_error(container_id));
+        }
+        exit_container(&mut state, container_id);
+        state.containers.remove(container_id);
+        Ok(())
+    }
+
+    async fn inspect_container(
+        &self,
+        container_id: &str,
+    ) -> Result<ContainerInspectResponse, Error> {
+        let state = self.lock();
+        let container = &state
+            .containers
+            .get(container_id)
+            .ok_or_else(|| not_found_error(container_id))?
+            .container;
+        Ok(ContainerInspectResponse {
+            id: Some(container.id.clone()),
+            name: Some(format!("/{}", container.name)),
+            created: Some(container.created.to_rfc3339()),
+            image: Some(container.image_id.clone()),
+            state: Some(ContainerState {
+                running: Some(container.is_running),
+                ..Default::default()
+            }),
+            config: Some(ContainerConfig {
+                labels: container.config.labels.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn list_containers(&self, label: &str) -> Result<Vec<ContainerSummary>, Error> {
+        let (label_key, label_value) = label.split_once('=').unwrap_or((label, ""));
+        Ok(self
+            .containers()
+            .into_iter()
+            .filter(|container| {
+                container.is_running
+                    && container
+                        .config
+                        .labels
+                        .as_ref()
+                        .and_then(|labels| labels.get(label_key))
+                        .map(|value| value == label_value)
+                        .unwrap_or(false)
+            })
+            .map(|container| ContainerSummary {
+                id: Some(container.id),
+                names: Some(vec![format!("/{}", container.name)]),
+                image_id: Some(container.image_id),
+                created: Some(container.created.timestamp()),
+                labels: container.config.labels,
+                state: Some(if container.is_running {
+                    "running".to_string()
+                } else {
+                    "exited".to_string()
+                }),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    async fn attach_container(&self, container_id: &str) -> Result<AttachContainerResults, Error> {
+        if !self.lock().containers.contains_key(container_id) {
+            return Err(not_found_error(container_id));
+        }
+        Ok(AttachContainerResults {
+            output: Box::pin(futures::stream::empty()),
+            input: Box::pin(ConsoleInput {
+                container_id: container_id.to_string(),
+                state: self.state.clone(),
+                buffer: Vec::new(),
+            }),
+        })
+    }
+
+    fn logs(&self, container_id: &str) -> BoxStream<'static, Result<LogOutput, Error>> {
+        let mut state = self.lock();
+        match state.containers.get_mut(container_id) {
+            Some(entry) if entry.container.is_running => {
+                let (sender, receiver) = unbounded_channel();
+                entry.log_senders.push(sender);
+                UnboundedReceiverStream::new(receiver).boxed()
+            }
+            Some(_) => futures::stream::empty().boxed(),
+            None => {
+                futures::stream::once(futures::future::ready(Err(not_found_error(container_id))))
+                    .boxed()
+            }
+        }
+    }
+
+    fn stats(&self, _container_id: &str) -> BoxStream<'static, Result<Stats, Error>> {
+        futures::stream::empty().boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>> {
+        let (sender, receiver) = unbounded_channel();
+        self.lock().event_senders.push(sender);
+        UnboundedReceiverStream::new(receiver).boxed()
+    }
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error> {
+        match self.lock().images.get(image) {
+            Some(image_id) => Ok(ImageInspect {
+                id: Some(image_id.clone()),
+                ..Default::default()
+            }),
+            None => Err(Error::DockerResponseServerError {
+                status_code: 404,
+                message: format!("No such image: {}", image),
+            }),
+        }
+    }
+
+    fn pull_image(&self, image: &str) -> BoxStream<'static, Result<CreateImageInfo, Error>> {
+        let mut state = self.lock();
+        state.pulled_images.push(image.to_string());
+        let image_id = match state.registry.get(image) {
+            Some(image_id) => image_id.clone(),
+            None => {
+                return futures::stream::once(futures::future::ready(Err(
+                    Error::DockerResponseServerError {
+                        status_code: 404,
+                        message: format!("pull access denied for {}", image),
+                    },
+                )))
+                .boxed()
+            }
+        };
+        state.images.insert(image.to_string(), image_id.clone());
+        let statuses = [
+            (Some(image_id), "Pulling fs layer".to_string()),
+            (
+                None,
+                format!("Status: Downloaded newer image for {}", image),
+            ),
+        ];
+        futures::stream::iter(statuses.into_iter().map(|(id, status)| {
+            Ok(CreateImageInfo {
+                id,
+                status: Some(status),
+                ..Default::default()
+            })
+        }))
+        .boxed()
+    }
+}
+
+/// Records each line written to a container's stdin, so tests can see what was sent to the console.
+struct ConsoleInput {
+    container_id: String,
+    state: Arc<Mutex<FakeState>>,
+    buffer: Vec<u8>,
+}
+
+impl AsyncWrite for ConsoleInput {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(line_end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<_> = self.buffer.drain(..=line_end).collect();
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            if let Some(entry) = self
+                .state
+                .lock()
+                .unwrap()
+                .containers
+                .get_mut(&self.container_id)
+            {
+                entry.container.console_lines.push(line);
+            }
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,135 @@
+use super::ContainerRuntime;
+use async_trait::async_trait;
+use bollard::container::{
+    AttachContainerResults, Config, LogOutput, RemoveContainerOptions, Stats,
+};
+use bollard::errors::Error;
+use bollard::models::{
+    ContainerCreateResponse, ContainerInspectResponse, ContainerSummary, CreateImageInfo,
+    EventMessage, ImageInspect,
+};
+use bollard::Docker;
+use futures::stream::BoxStream;
+use log::debug;
+use std::time::Duration;
+
+/// Podman through its Docker-compatible API, working around the places where it behaves
+/// differently from Docker.
+pub struct PodmanRuntime {
+    docker: Docker,
+}
+
+impl PodmanRuntime {
+    pub fn new(docker: Docker) -> Self {
+        PodmanRuntime { docker }
+    }
+
+    /// Removes a container that has exited, since it isn't removed automatically.
+    async fn remove_exited(&self, container_id: &str) -> Result<(), Error> {
+        match Docker::remove_container(&self.docker, container_id, None).await {
+            Ok(()) => Ok(()),
+            Err(Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(()),
+            Err(why) => Err(why),
+        }
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for PodmanRuntime {
+    async fn create_container(
+        &self,
+        name: &str,
+        mut config: Config<String>,
+    ) -> Result<ContainerCreateResponse, Error> {
+        if let Some(host_config) = &mut config.host_config {
+            // Podman auto-removes containers from a separate cleanup process, so the name can
+            // still be taken when the server is started again. They're removed when they're
+            // stopped instead.
+            host_config.auto_remove = Some(false);
+
+            // Podman doesn't have the local log driver, but k8s-file is much the same
+            if let Some(log_config) = &mut host_config.log_config {
+                if log_config.typ.as_deref() == Some("local") {
+                    log_config.typ = Some("k8s-file".to_string());
+                }
+            }
+        }
+
+        // Clear out the container from the last run, if it exited on its own
+        if let Err(why) = self.remove_exited(name).await {
+            debug!("Couldn't remove old container {}: {}", name, why);
+        }
+        ContainerRuntime::create_container(&self.docker, name, config).await
+    }
+
+    async fn start_container(&self, container_id: &str) -> Result<(), Error> {
+        ContainerRuntime::start_container(&self.docker, container_id).await
+    }
+
+    async fn stop_container(&self, container_id: &str, timeout: Duration) -> Result<(), Error> {
+        // Stopping fails if the container has already stopped, but it still has to be removed
+        let stop_result =
+            ContainerRuntime::stop_container(&self.docker, container_id, timeout).await;
+        match self.remove_exited(container_id).await {
+            Ok(()) => Ok(()),
+            Err(why) => {
+                stop_result?;
+                Err(why)
+            }
+        }
+    }
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), Error> {
+        // Killing doesn't wait for the container to exit, so remove it by force instead
+        Docker::remove_container(
+            &self.docker,
+            container_id,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+    }
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), Error> {
+        ContainerRuntime::remove_container(&self.docker, container_id).await
+    }
+
+    async fn inspect_container(
+        &self,
+        container_id: &str,
+    ) -> Result<ContainerInspectResponse, Error> {
+        ContainerRuntime::inspect_container(&self.docker, container_id).await
+    }
+
+    async fn list_containers(&self, label: &str) -> Result<Vec<ContainerSummary>, Error> {
+        ContainerRuntime::list_containers(&self.docker, label).await
+    }
+
+    async fn attach_container(&self, container_id: &str) -> Result<AttachContainerResults, Error> {
+        ContainerRuntime::attach_container(&self.docker, container_id).await
+    }
+
+    fn logs(&self, container_id: &str) -> BoxStream<'static, Result<LogOutput, Error>> {
+        ContainerRuntime::logs(&self.docker, container_id)
+    }
+
+    fn stats(&self, container_id: &str) -> BoxStream<'static, Result<Stats, Error>> {
+        ContainerRuntime::stats(&self.docker, container_id)
+    }
+
+    fn events(&self) -> BoxStream<'static, Result<EventMessage, Error>> {
+        ContainerRuntime::events(&self.docker)
+    }
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, Error> {
+        ContainerRuntime::inspect_image(&self.docker, image).await
+    }
+
+    fn pull_image(&self, image: &str) -> BoxStream<'static, Result<CreateImageInfo, Error>> {
+        ContainerRuntime::pull_image(&self.docker, image)
+    }
+}
@@ -1,23 +1,50 @@
-use crate::arg_builder::ArgBuilder;
-use crate::config::FilledInstanceConfig;
+use crate::arg_builder::{ArgBuilder, BuiltArgs};
+use crate::config::{
+    FilledGameConfig, FilledInstanceConfig, NotificationEvent, ReadyCheck, RestartPolicy,
+};
+use crate::console::write_console_command;
+use crate::container_watcher::{ContainerEvent, CONTAINER_NAME_PREFIX};
+use crate::hosts::{Host, HostLimits};
+use crate::images::{self, ImageCheck};
+use crate::log_parser::{ConfigPatterns, GameLogEvent, GameLogEventKind, GameLogParser};
+use crate::logs::{spawn_log_pipe, LogPolicy};
+use crate::metrics::record_docker_error;
+use crate::notifications::Notifier;
+use crate::readiness;
+use crate::restart_backoff::{seconds_to_duration, FailureOutcome, RestartBackoff};
+use crate::rollout::Rollout;
+use crate::runtime::ContainerRuntime;
 use crate::Config;
-use bollard::container::{CreateContainerOptions, LogsOptions};
 use bollard::models::{
     ContainerInspectResponse, ContainerState, HostConfig, HostConfigLogConfig, PortBinding,
 };
-use bollard::Docker;
-use chrono::{DateTime, Datelike, Timelike, Utc};
-use futures::StreamExt;
+use chrono::{DateTime, TimeZone, Utc};
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tokio::time::sleep;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Instant};
+
+const INSTANCE_LABEL: &str = "r2wraith.instance";
+const SERVER_LABEL: &str = "r2wraith.server";
+const GAME_PORT_LABEL: &str = "r2wraith.game-port";
+const AUTH_PORT_LABEL: &str = "r2wraith.auth-port";
+const CONFIG_HASH_LABEL: &str = "r2wraith.config-hash";
+
+/// How long to give the runtime to stop a container after the stop timeout, before killing it
+/// ourselves.
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How long to wait for a container to go away after killing or removing it.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 enum StartServerError {
@@ -40,6 +67,53 @@ impl Display for StartServerError {
 
 impl std::error::Error for StartServerError {}
 
+#[derive(Debug)]
+enum StopServerError {
+    ContainerNotRemoved(bollard::errors::Error),
+    TimedOut,
+    Abandoned,
+}
+
+impl Display for StopServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopServerError::ContainerNotRemoved(err) => {
+                write!(f, "The container could not be removed: {}", err)
+            }
+            StopServerError::TimedOut => {
+                write!(
+                    f,
+                    "The container still exists after being killed and removed"
+                )
+            }
+            StopServerError::Abandoned => {
+                write!(f, "The stop was abandoned before it finished")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StopServerError {}
+
+/// Why a server couldn't be given a host and ports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlacementError {
+    /// Every host the server can go on is at its capacity, so it waits for another server to stop.
+    HostsFull(String),
+    /// The server can't go anywhere, such as when there are no free ports.
+    Unplaceable(String),
+}
+
+impl Display for PlacementError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlacementError::HostsFull(message) | PlacementError::Unplaceable(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
 pub enum PollStatus {
     DidWork,
     NoWork,
@@ -47,15 +121,152 @@ pub enum PollStatus {
 
 #[derive(Debug)]
 pub struct RunningServer {
+    host: Arc<Host>,
     container_id: String,
+    /// The ID of the image the container was created from.
+    image_id: Option<String>,
     game_port: u16,
+    auth_port: Option<u16>,
     start_time: DateTime<Utc>,
+    /// When the game server was ready to take players, which is after its container started.
+    /// Servers that are restored or rediscovered were already running, so when they became ready
+    /// isn't known and this is their start time instead. Leaving it unset would have them fail
+    /// their start timeout.
+    ready_time: Option<DateTime<Utc>>,
+    /// Only known if we've been parsing the server's logs since it started.
+    game_state: Option<GameState>,
+    /// The last restart warning that was sent to the server's console, in minutes.
+    last_restart_warning_minutes: Option<u32>,
+    is_waiting_to_restart: bool,
+}
+
+impl RunningServer {
+    /// Whether the game is at a point where the restart policy allows a restart. If the game
+    /// state isn't known, this waits for the max wait.
+    fn can_restart(&self, game_config: &FilledGameConfig) -> bool {
+        match (game_config.restart_policy, &self.game_state) {
+            (RestartPolicy::Immediate, _) => true,
+            (RestartPolicy::WhenEmpty, Some(game_state)) => game_state.players.is_empty(),
+            // Matches can only be tracked with patterns for both ends of them
+            (RestartPolicy::AfterMatch, Some(game_state))
+                if game_config.match_start_log_pattern.is_some()
+                    && game_config.match_end_log_pattern.is_some() =>
+            {
+                !game_state.is_match_in_progress
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What's happening in the game, according to the server's log output.
+#[derive(Debug, Default)]
+pub struct GameState {
+    pub players: BTreeSet<String>,
+    pub is_match_in_progress: bool,
 }
 
 #[derive(Debug)]
 pub enum ServerState {
     NotRunning,
     Running(RunningServer),
+    Stopping(StoppingServer),
+    CrashLooping,
+    Stopped,
+}
+
+/// A server whose container is being stopped in the background, which can take as long as the
+/// stop timeout plus the time to kill and remove it.
+#[derive(Debug)]
+pub struct StoppingServer {
+    /// Kept so the server can go back to running if its container couldn't be stopped, and so its
+    /// ports aren't given to another server before they're free.
+    running_server: RunningServer,
+    after_stop: AfterStop,
+    result_receiver: oneshot::Receiver<Result<(), StopServerError>>,
+}
+
+/// What happens to a server once its container has stopped.
+#[derive(Debug)]
+enum AfterStop {
+    Stop,
+    Restart(RestartReason),
+    Hold,
+    /// The server wasn't ready in time, so this counts as a failure with the given message.
+    FailedStart(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServerStateKind {
+    NotRunning,
+    Pulling,
+    Starting,
+    Running,
+    Stopping,
+    CrashLooping,
+    Stopped,
+}
+
+impl Display for ServerStateKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerStateKind::NotRunning => write!(f, "not running"),
+            ServerStateKind::Pulling => write!(f, "pulling"),
+            ServerStateKind::Starting => write!(f, "starting"),
+            ServerStateKind::Running => write!(f, "running"),
+            ServerStateKind::Stopping => write!(f, "stopping"),
+            ServerStateKind::CrashLooping => write!(f, "crash-looping"),
+            ServerStateKind::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RestartReason {
+    Crash,
+    Schedule,
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RestartCounts {
+    pub crash: u32,
+    pub schedule: u32,
+    pub manual: u32,
+}
+
+impl RestartCounts {
+    fn record(&mut self, reason: RestartReason) {
+        match reason {
+            RestartReason::Crash => self.crash += 1,
+            RestartReason::Schedule => self.schedule += 1,
+            RestartReason::Manual => self.manual += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.crash + self.schedule + self.manual
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatus {
+    pub id: String,
+    pub name: String,
+    pub state: ServerStateKind,
+    pub is_old: bool,
+    pub host: Option<String>,
+    pub container_id: Option<String>,
+    pub game_port: Option<u16>,
+    pub auth_port: Option<u16>,
+    pub start_time: Option<DateTime<Utc>>,
+    pub uptime_seconds: Option<i64>,
+    pub next_restart_time: Option<DateTime<Utc>>,
+    pub restart_counts: RestartCounts,
+    pub players: Option<Vec<String>>,
+    /// Whether a newer copy of the server's image has been pulled since its container was created.
+    pub update_available: bool,
 }
 
 #[derive(Debug)]
@@ -64,18 +275,45 @@ pub struct Server {
     pub config: FilledInstanceConfig,
     pub state: ServerState,
     pub is_old: bool,
+    restart_backoff: RestartBackoff,
+    restart_counts: RestartCounts,
+    /// Set when the server is restarted on purpose, which is when `on-restart` pulls its image.
+    is_planned_restart: bool,
+    /// Crashes and failed starts since R2Wraith started.
+    failure_count: u32,
+    /// A pull that has to finish before the server can be started.
+    image_pull: Option<ImagePull>,
+    /// Why the server couldn't be given a host and ports on the last try, if it couldn't.
+    placement_error: Option<PlacementError>,
+}
+
+/// An image being pulled on a host in the background.
+#[derive(Debug)]
+struct ImagePull {
+    host_name: String,
+    image: String,
+    task: JoinHandle<Result<(), String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SerializedServer {
     pub name: String,
+    /// Missing from restore files written before hosts were added, which only had one host.
+    #[serde(default)]
+    pub host: Option<String>,
     pub container_id: String,
     pub game_port: u16,
+    #[serde(default)]
+    pub auth_port: Option<u16>,
 }
 
-#[derive(Default)]
 pub struct ServerCluster {
+    hosts: Vec<Arc<Host>>,
     servers: Vec<Server>,
+    rollout: Option<Rollout>,
+    notifier: Notifier,
+    game_log_sender: UnboundedSender<GameLogEvent>,
+    stop_notify: Arc<Notify>,
 }
 
 impl Server {
@@ -85,63 +323,31 @@ impl Server {
             config,
             state: ServerState::NotRunning,
             is_old: false,
+            restart_backoff: RestartBackoff::default(),
+            restart_counts: RestartCounts::default(),
+            is_planned_restart: false,
+            failure_count: 0,
+            image_pull: None,
+            placement_error: None,
         }
     }
 
-    pub async fn start(&mut self, game_port: u16, docker: &Docker) -> Result<(), Box<dyn Error>> {
-        let mut env_vars = Vec::new();
+    pub fn build_args(&self, game_port: u16, auth_port: Option<u16>) -> BuiltArgs {
         ArgBuilder::new()
             .set_name(self.config.name.clone())
             .set_game_port(game_port)
+            .set_auth_port(auth_port)
             .set_game_config(self.config.game_config.clone())
-            .build(&mut env_vars);
-
-        info!("Starting {} with game port {}", self.id, game_port);
-        debug!("Environment variables:");
-        for env_var in &env_vars {
-            debug!("  {}", env_var);
-        }
-
-        // Ensure the log directory exists
-        if let Err(why) = tokio::fs::create_dir_all(&self.config.game_config.logs_dir).await {
-            warn!(
-                "Failed to create log directory {}: {}",
-                self.config.game_config.logs_dir, why
-            );
-        }
+            .finish()
+    }
 
-        let start_time = Utc::now();
-        let log_file_path = Path::new(&self.config.game_config.logs_dir).join(format!(
-            "{} {}-{}-{} {}-{}-{}.txt",
-            self.id,
-            start_time.year(),
-            start_time.month(),
-            start_time.day(),
-            start_time.hour(),
-            start_time.minute(),
-            start_time.second()
-        ));
-
-        let maybe_log_file = match OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&log_file_path)
-            .await
-        {
-            Ok(file) => {
-                info!("Writing logs to {}", log_file_path.display());
-                Some(file)
-            }
-            Err(why) => {
-                warn!(
-                    "Failed to open log file {}: {}",
-                    log_file_path.display(),
-                    why
-                );
-                None
-            }
-        };
+    fn build_env_vars(&self, game_port: u16, auth_port: Option<u16>) -> Vec<String> {
+        let mut env_vars = Vec::new();
+        self.build_args(game_port, auth_port).build(&mut env_vars);
+        env_vars
+    }
 
+    fn build_binds(&self) -> Vec<String> {
         let mut binds = vec![format!(
             "{}:/mnt/titanfall",
             self.config.game_config.game_dir
@@ -153,31 +359,63 @@ impl Server {
                 .map(|mod_name| format!("{}:/mnt/mods/{}:ro", mod_dir, mod_name))
         }));
         binds.extend(self.config.game_config.extra_binds.iter().cloned());
+        binds
+    }
+
+    /// Builds the container spec that's sent to the runtime when the server is started.
+    pub fn build_container_config(
+        &self,
+        game_port: u16,
+        auth_port: Option<u16>,
+        instance_id: &str,
+    ) -> bollard::container::Config<String> {
+        let binds = self.build_binds();
+        let mut labels: HashMap<_, _> = [
+            (INSTANCE_LABEL.to_string(), instance_id.to_string()),
+            (SERVER_LABEL.to_string(), self.id.clone()),
+            (GAME_PORT_LABEL.to_string(), game_port.to_string()),
+            (
+                CONFIG_HASH_LABEL.to_string(),
+                self.config_hash(game_port, auth_port),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let mut port_bindings = vec![(
+            format!("{}/udp", game_port),
+            Some(vec![PortBinding {
+                host_ip: None,
+                host_port: Some(game_port.to_string()),
+            }]),
+        )];
+        if let Some(auth_port) = auth_port {
+            labels.insert(AUTH_PORT_LABEL.to_string(), auth_port.to_string());
+            port_bindings.push((
+                format!("{}/tcp", auth_port),
+                Some(vec![PortBinding {
+                    host_ip: None,
+                    host_port: Some(auth_port.to_string()),
+                }]),
+            ));
+        }
 
-        let container_config = bollard::container::Config {
+        bollard::container::Config {
             image: Some(self.config.game_config.docker_image.clone()),
+            labels: Some(labels),
             attach_stdout: Some(true),
             attach_stderr: Some(true),
             attach_stdin: Some(true),
-            env: Some(env_vars),
+            open_stdin: Some(true),
+            env: Some(self.build_env_vars(game_port, auth_port)),
             exposed_ports: Some(
-                [(format!("{}/udp", game_port), HashMap::new())]
-                    .into_iter()
+                port_bindings
+                    .iter()
+                    .map(|(port, _)| (port.clone(), HashMap::new()))
                     .collect(),
             ),
             host_config: Some(HostConfig {
                 binds: Some(binds),
-                port_bindings: Some(
-                    [(
-                        format!("{}/udp", game_port),
-                        Some(vec![PortBinding {
-                            host_ip: None,
-                            host_port: Some(game_port.to_string()),
-                        }]),
-                    )]
-                    .into_iter()
-                    .collect(),
-                ),
+                port_bindings: Some(port_bindings.into_iter().collect()),
                 auto_remove: Some(true),
 
                 memory: self.config.game_config.perf_memory_limit_bytes,
@@ -198,15 +436,110 @@ impl Server {
                 ..Default::default()
             }),
             ..Default::default()
-        };
-        let create_response = docker
-            .create_container(
-                Some(CreateContainerOptions {
-                    name: format!("r2wraith-{}", self.id),
-                }),
-                container_config,
-            )
-            .await?;
+        }
+    }
+
+    /// Hashes everything that goes into the container spec, so a container can be checked against
+    /// the current config after it's been rediscovered.
+    fn config_hash(&self, game_port: u16, auth_port: Option<u16>) -> String {
+        let game_config = &self.config.game_config;
+        let mut env_vars = self.build_env_vars(game_port, auth_port);
+        env_vars.sort();
+        let mut binds = self.build_binds();
+        binds.sort();
+        let perf_limits = format!(
+            "{:?} {:?} {:?} {:?}",
+            game_config.perf_memory_limit_bytes,
+            game_config.perf_virtual_memory_limit_bytes,
+            game_config.perf_cpus,
+            game_config.perf_cpu_set
+        );
+
+        // 64-bit FNV-1a, which is stable between builds unlike the std hasher
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for part in std::iter::once(&game_config.docker_image)
+            .chain(&env_vars)
+            .chain(&binds)
+            .chain(std::iter::once(&perf_limits))
+        {
+            for byte in part.bytes().chain(std::iter::once(0)) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        format!("{:016x}", hash)
+    }
+
+    pub async fn start(
+        &mut self,
+        host: &Arc<Host>,
+        game_port: u16,
+        auth_port: Option<u16>,
+        instance_id: &str,
+        game_log_sender: &UnboundedSender<GameLogEvent>,
+    ) -> Result<(), Box<dyn Error>> {
+        let runtime = host.runtime.as_ref();
+        let game_config = &self.config.game_config;
+        let image_pull = self.image_pull.take().filter(|image_pull| {
+            image_pull.host_name == host.name && image_pull.image == game_config.docker_image
+        });
+        match image_pull {
+            Some(image_pull) => image_pull
+                .task
+                .await
+                .map_err(|why| format!("Pulling {} failed: {}", game_config.docker_image, why))??,
+            None => {
+                let image_check = images::check_image(
+                    host,
+                    &game_config.docker_image,
+                    game_config.image_pull_policy,
+                    self.is_planned_restart,
+                )
+                .await?;
+                if let ImageCheck::NeedsPull(local_image_id) = image_check {
+                    // The server is started on a later poll, once the pull has finished
+                    self.image_pull = Some(ImagePull {
+                        host_name: host.name.clone(),
+                        image: game_config.docker_image.clone(),
+                        task: images::spawn_pull(
+                            host.clone(),
+                            game_config.docker_image.clone(),
+                            local_image_id,
+                        ),
+                    });
+                    return Ok(());
+                }
+            }
+        }
+        // Only forget about the planned restart once the image is ready, so a failed pull is tried
+        // again next time
+        self.is_planned_restart = false;
+
+        let container_config = self.build_container_config(game_port, auth_port, instance_id);
+
+        match auth_port {
+            Some(auth_port) => info!(
+                "Starting {} on {} with game port {} and auth port {}",
+                self.id, host.name, game_port, auth_port
+            ),
+            None => info!(
+                "Starting {} on {} with game port {}",
+                self.id, host.name, game_port
+            ),
+        }
+        debug!("Environment variables:");
+        for env_var in container_config.env.iter().flatten() {
+            debug!("  {}", env_var);
+        }
+
+        let log_policy = LogPolicy::new(&self.id, &self.config.game_config);
+        let maybe_log_file = log_policy.create_log_file(Utc::now()).await;
+        log_policy.apply_retention().await;
+
+        let create_response = runtime
+            .create_container(&container_name(instance_id, &self.id), container_config)
+            .await
+            .inspect_err(record_docker_error)?;
         if !create_response.warnings.is_empty() {
             for warning in &create_response.warnings {
                 warn!("{}", warning);
@@ -214,86 +547,490 @@ impl Server {
         }
 
         let container_id = create_response.id;
-        docker
-            .start_container::<String>(&container_id, None)
-            .await?;
-
-        if let Some(mut log_file) = maybe_log_file {
-            let mut log_stream = docker.logs::<String>(
-                &container_id,
-                Some(LogsOptions {
-                    follow: true,
-                    stdout: true,
-                    stderr: true,
-                    ..Default::default()
-                }),
-            );
-            tokio::spawn(async move {
-                let maybe_res: Result<(), Box<dyn Error>> = async {
-                    while let Some(v) = log_stream.next().await {
-                        let stripped_v = strip_ansi_escapes::strip(v?.into_bytes())?;
-                        log_file.write_all(&stripped_v).await?;
-                    }
-                    Ok(())
-                }
-                .await;
+        runtime
+            .start_container(&container_id)
+            .await
+            .inspect_err(record_docker_error)?;
 
-                if let Err(why) = maybe_res {
-                    warn!("Failed to pipe logs: {}", why);
-                }
-                info!("Finished piping logs!");
-            });
-        }
+        let log_stream = runtime.logs(&container_id);
+        let ready_check = match (game_config.ready_check, &game_config.ready_log_pattern) {
+            (ReadyCheck::Log, None) => {
+                warn!(
+                    "Server {} has ready-check = \"log\" without a ready-log-pattern, so it's ready once started",
+                    self.id
+                );
+                ReadyCheck::None
+            }
+            (ready_check, _) => ready_check,
+        };
+        let config_patterns = ConfigPatterns {
+            ready: match ready_check {
+                ReadyCheck::Log => game_config
+                    .ready_log_pattern
+                    .as_ref()
+                    .map(|ready_log_pattern| ready_log_pattern.0.clone()),
+                ReadyCheck::None | ReadyCheck::Udp => None,
+            },
+            match_start: game_config
+                .match_start_log_pattern
+                .as_ref()
+                .map(|match_start_log_pattern| match_start_log_pattern.0.clone()),
+            match_end: game_config
+                .match_end_log_pattern
+                .as_ref()
+                .map(|match_end_log_pattern| match_end_log_pattern.0.clone()),
+        };
+        let log_parser = GameLogParser::new(
+            self.id.clone(),
+            container_id.clone(),
+            game_log_sender.clone(),
+            config_patterns,
+        );
+        spawn_log_pipe(log_stream, maybe_log_file, log_policy, log_parser);
 
-        let inspect_response = docker
-            .inspect_container(&container_id, None)
+        let inspect_response = runtime
+            .inspect_container(&container_id)
             .await
+            .inspect_err(record_docker_error)
             .map_err(StartServerError::ContainerDidntStart)?;
         let start_time = get_container_created(&inspect_response)
             .ok_or(StartServerError::ContainerHasNoCreated)?;
 
-        info!("Server {} has been started", self.id);
+        let ready_time = match ready_check {
+            ReadyCheck::None => {
+                info!("Server {} has been started", self.id);
+                Some(start_time)
+            }
+            ReadyCheck::Log | ReadyCheck::Udp => {
+                info!(
+                    "Container for {} has been started, waiting for it to be ready",
+                    self.id
+                );
+                None
+            }
+        };
 
+        self.restart_backoff.record_start(start_time);
         self.state = ServerState::Running(RunningServer {
+            host: host.clone(),
             container_id,
+            image_id: inspect_response.image,
             game_port,
+            auth_port,
             start_time,
+            ready_time,
+            game_state: Some(GameState::default()),
+            last_restart_warning_minutes: None,
+            is_waiting_to_restart: false,
         });
         Ok(())
     }
 
-    pub async fn stop(&mut self, docker: &Docker) {
-        if let ServerState::Running(running_server) = &self.state {
-            if let Err(why) = docker
-                .stop_container(&running_server.container_id, None)
+    /// Starts stopping the server's container in the background, and notifies `stop_notify` once
+    /// it's done so the result can be picked up by [`Server::poll_stop`]. Servers that aren't
+    /// running go straight to what would happen after the stop.
+    fn begin_stop(&mut self, after_stop: AfterStop, stop_notify: &Arc<Notify>) {
+        match std::mem::replace(&mut self.state, ServerState::NotRunning) {
+            ServerState::Running(running_server) => {
+                let (result_sender, result_receiver) = oneshot::channel();
+                let server_id = self.id.clone();
+                let container_id = running_server.container_id.clone();
+                let host = running_server.host.clone();
+                let stop_timeout =
+                    Duration::from_secs_f64(self.config.game_config.stop_timeout_seconds);
+                let stop_notify = stop_notify.clone();
+                tokio::spawn(async move {
+                    let result = stop_container(
+                        &server_id,
+                        &container_id,
+                        stop_timeout,
+                        host.runtime.as_ref(),
+                    )
+                    .await;
+                    let _ = result_sender.send(result);
+                    stop_notify.notify_one();
+                });
+                self.state = ServerState::Stopping(StoppingServer {
+                    running_server,
+                    after_stop,
+                    result_receiver,
+                });
+            }
+            ServerState::Stopping(mut stopping_server) => {
+                // The stop is already underway, so only what happens after it changes
+                stopping_server.after_stop = after_stop;
+                self.state = ServerState::Stopping(stopping_server);
+            }
+            _ => match after_stop {
+                AfterStop::Restart(reason) => self.record_restart(reason),
+                AfterStop::Hold => self.state = ServerState::Stopped,
+                AfterStop::Stop | AfterStop::FailedStart(_) => {}
+            },
+        }
+    }
+
+    /// Moves the server on from stopping if its container has finished stopping.
+    fn poll_stop(&mut self, time: DateTime<Utc>, notifier: &Notifier) {
+        let result = match &mut self.state {
+            ServerState::Stopping(stopping_server) => {
+                match stopping_server.result_receiver.try_recv() {
+                    Ok(result) => result,
+                    Err(TryRecvError::Empty) => return,
+                    Err(TryRecvError::Closed) => Err(StopServerError::Abandoned),
+                }
+            }
+            _ => return,
+        };
+        self.finish_stop(result, time, notifier);
+    }
+
+    /// Waits for the server's container to finish stopping, if it's being stopped.
+    async fn wait_for_stop(&mut self, time: DateTime<Utc>, notifier: &Notifier) {
+        let result = match &mut self.state {
+            ServerState::Stopping(stopping_server) => (&mut stopping_server.result_receiver)
                 .await
-            {
-                error!("Failed to stop {}: {}", self.id, why);
+                .unwrap_or(Err(StopServerError::Abandoned)),
+            _ => return,
+        };
+        self.finish_stop(result, time, notifier);
+    }
+
+    fn finish_stop(
+        &mut self,
+        result: Result<(), StopServerError>,
+        time: DateTime<Utc>,
+        notifier: &Notifier,
+    ) {
+        let stopping_server = match std::mem::replace(&mut self.state, ServerState::NotRunning) {
+            ServerState::Stopping(stopping_server) => stopping_server,
+            state => {
+                self.state = state;
                 return;
             }
+        };
+        if let Err(why) = result {
+            error!("Failed to stop {}: {}", self.id, why);
+            self.state = ServerState::Running(stopping_server.running_server);
+            return;
+        }
+
+        info!("Stopped {}", self.id);
+        match stopping_server.after_stop {
+            AfterStop::Stop => {}
+            AfterStop::Restart(reason) => self.record_restart(reason),
+            AfterStop::Hold => self.state = ServerState::Stopped,
+            AfterStop::FailedStart(message) => {
+                notifier.notify(NotificationEvent::StartFailed, self, message);
+                self.record_failure(time, notifier);
+            }
+        }
+    }
 
-            // Wait for the container to actually stop
-            loop {
-                if docker
-                    .inspect_container(&running_server.container_id, None)
-                    .await
-                    .is_err()
-                {
-                    info!("Stopped {}", self.id);
-                    break;
+    pub fn stop(&mut self, stop_notify: &Arc<Notify>) {
+        self.begin_stop(AfterStop::Stop, stop_notify);
+    }
+
+    pub fn restart(&mut self, reason: RestartReason, stop_notify: &Arc<Notify>) {
+        self.begin_stop(AfterStop::Restart(reason), stop_notify);
+    }
+
+    fn record_restart(&mut self, reason: RestartReason) {
+        self.restart_counts.record(reason);
+        self.is_planned_restart = !matches!(reason, RestartReason::Crash);
+    }
+
+    /// Restarts the server once its restart schedule has passed and the restart policy allows it,
+    /// warning players through the console beforehand.
+    async fn poll_restart_schedule(
+        &mut self,
+        poll_time: DateTime<Utc>,
+        notifier: &Notifier,
+        stop_notify: &Arc<Notify>,
+    ) {
+        let game_config = &self.config.game_config;
+        let running_server = match &mut self.state {
+            ServerState::Running(running_server) => running_server,
+            _ => return,
+        };
+        let restart_time = match &game_config.restart_schedule {
+            Some(schedule) => match schedule.after(&running_server.start_time).next() {
+                Some(restart_time) => restart_time,
+                None => return,
+            },
+            None => return,
+        };
+
+        // Servers that are waiting for the game are still restarted after the max wait
+        let deadline = match game_config.restart_policy {
+            RestartPolicy::Immediate => restart_time,
+            RestartPolicy::WhenEmpty | RestartPolicy::AfterMatch => {
+                restart_time + seconds_to_duration(game_config.restart_max_wait_seconds)
+            }
+        };
+
+        if poll_time < restart_time {
+            let remaining_minutes =
+                ((restart_time - poll_time).num_seconds() as f64 / 60.).ceil() as u32;
+            let next_warning_minutes = game_config
+                .restart_warning_minutes
+                .iter()
+                .copied()
+                .filter(|&warning_minutes| remaining_minutes <= warning_minutes)
+                .min();
+            if let Some(warning_minutes) = next_warning_minutes {
+                let is_new_warning = running_server
+                    .last_restart_warning_minutes
+                    .map(|last_warning_minutes| warning_minutes < last_warning_minutes)
+                    .unwrap_or(true);
+                if is_new_warning {
+                    running_server.last_restart_warning_minutes = Some(warning_minutes);
+                    send_restart_warning(&self.id, running_server, game_config, remaining_minutes)
+                        .await;
                 }
+            }
+            return;
+        }
+
+        if poll_time < deadline && !running_server.can_restart(game_config) {
+            if !running_server.is_waiting_to_restart {
+                running_server.is_waiting_to_restart = true;
+                info!(
+                    "Server {} has passed a scheduled restart, waiting for it to be {} first",
+                    self.id,
+                    match game_config.restart_policy {
+                        RestartPolicy::AfterMatch => "between matches",
+                        _ => "empty",
+                    }
+                );
+            }
+            return;
+        }
+
+        // The earlier warnings could have been a while ago if the restart waited for the game
+        if game_config.restart_policy != RestartPolicy::Immediate
+            && !game_config.restart_warning_minutes.is_empty()
+        {
+            send_restart_warning(&self.id, running_server, game_config, 0).await;
+        }
+
+        let message = format!("Server {} has passed a scheduled restart", self.id);
+        warn!("{}", message);
+        notifier.notify(NotificationEvent::ScheduledRestart, self, message);
+        self.restart(RestartReason::Schedule, stop_notify);
+    }
+
+    /// Checks whether a server that's starting has become ready, and stops it as a failed start if
+    /// it isn't ready within `start-timeout-seconds`.
+    async fn poll_readiness(&mut self, poll_time: DateTime<Utc>, stop_notify: &Arc<Notify>) {
+        let game_config = &self.config.game_config;
+        let running_server = match &mut self.state {
+            ServerState::Running(running_server) if running_server.ready_time.is_none() => {
+                running_server
+            }
+            _ => return,
+        };
+
+        if game_config.ready_check == ReadyCheck::Udp
+            && readiness::probe_udp(
+                &running_server.host.game_address,
+                running_server.game_port,
+                &game_config.ready_udp_payload,
+            )
+            .await
+        {
+            info!("Server {} is ready", self.id);
+            running_server.ready_time = Some(poll_time);
+            return;
+        }
+
+        if poll_time - running_server.start_time
+            <= seconds_to_duration(game_config.start_timeout_seconds)
+        {
+            return;
+        }
+
+        let message = format!(
+            "Could not start {}: it wasn't ready within {} seconds",
+            self.id, game_config.start_timeout_seconds
+        );
+        error!("{}", message);
+        self.begin_stop(AfterStop::FailedStart(message), stop_notify);
+    }
+
+    fn record_crash(&mut self, time: DateTime<Utc>, message: String, notifier: &Notifier) {
+        warn!("{}", message);
+        notifier.notify(NotificationEvent::Crash, self, message);
+        self.restart_counts.record(RestartReason::Crash);
+        self.record_failure(time, notifier);
+    }
+
+    fn record_failure(&mut self, time: DateTime<Utc>, notifier: &Notifier) {
+        self.failure_count += 1;
+        match self
+            .restart_backoff
+            .record_failure(time, &self.config.game_config)
+        {
+            FailureOutcome::RetryAfter(delay) => {
+                if delay > chrono::Duration::zero() {
+                    info!(
+                        "Waiting {} seconds before restarting {}",
+                        delay.num_milliseconds() as f64 / 1000.,
+                        self.id
+                    );
+                }
+                self.state = ServerState::NotRunning;
+            }
+            FailureOutcome::CrashLooping => {
+                let message = format!(
+                    "Server {} keeps failing and won't be restarted, use the \"start\" command to try again",
+                    self.id
+                );
+                error!("{}", message);
+                notifier.notify(NotificationEvent::CrashLooping, self, message);
+                self.state = ServerState::CrashLooping;
+            }
+        }
+    }
+
+    /// Stops the server and keeps it stopped until [`Server::resume`] is called.
+    pub fn hold(&mut self, stop_notify: &Arc<Notify>) {
+        self.begin_stop(AfterStop::Hold, stop_notify);
+    }
+
+    pub fn resume(&mut self) -> bool {
+        if let ServerState::CrashLooping | ServerState::Stopped = self.state {
+            self.restart_backoff.reset();
+            self.state = ServerState::NotRunning;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_pulling_image(&self) -> bool {
+        self.image_pull
+            .as_ref()
+            .map(|image_pull| !image_pull.task.is_finished())
+            .unwrap_or(false)
+    }
+
+    pub fn failure_count(&self) -> u32 {
+        self.failure_count
+    }
+
+    /// Whether the server is running and has passed its ready check.
+    pub fn is_ready(&self) -> bool {
+        matches!(
+            &self.state,
+            ServerState::Running(RunningServer {
+                ready_time: Some(_),
+                ..
+            })
+        )
+    }
+
+    pub fn container_id(&self) -> Option<&str> {
+        match &self.state {
+            ServerState::Running(running_server) => Some(&running_server.container_id),
+            _ => None,
+        }
+    }
 
-                debug!("Waiting for {} to stop", self.id);
-                sleep(Duration::from_millis(100)).await;
+    /// The host the server is running on.
+    pub fn host(&self) -> Option<&Arc<Host>> {
+        match &self.state {
+            ServerState::Running(running_server) => Some(&running_server.host),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        let (state, running_server) = match &self.state {
+            ServerState::NotRunning if self.is_pulling_image() => (ServerStateKind::Pulling, None),
+            ServerState::NotRunning => (ServerStateKind::NotRunning, None),
+            ServerState::Running(running_server) if running_server.ready_time.is_none() => {
+                (ServerStateKind::Starting, Some(running_server))
+            }
+            ServerState::Running(running_server) => {
+                (ServerStateKind::Running, Some(running_server))
             }
+            ServerState::Stopping(stopping_server) => (
+                ServerStateKind::Stopping,
+                Some(&stopping_server.running_server),
+            ),
+            ServerState::CrashLooping => (ServerStateKind::CrashLooping, None),
+            ServerState::Stopped => (ServerStateKind::Stopped, None),
+        };
+        let game_state =
+            running_server.and_then(|running_server| running_server.game_state.as_ref());
+        ServerStatus {
+            id: self.id.clone(),
+            name: self.config.name.clone(),
+            state,
+            is_old: self.is_old,
+            host: running_server.map(|running_server| running_server.host.name.clone()),
+            container_id: running_server.map(|running_server| running_server.container_id.clone()),
+            game_port: running_server.map(|running_server| running_server.game_port),
+            auth_port: running_server.and_then(|running_server| running_server.auth_port),
+            start_time: running_server.map(|running_server| running_server.start_time),
+            uptime_seconds: running_server.map(|running_server| {
+                (running_server.host.runtime.now() - running_server.start_time).num_seconds()
+            }),
+            next_restart_time: running_server.and_then(|running_server| {
+                self.config
+                    .game_config
+                    .restart_schedule
+                    .as_ref()
+                    .and_then(|schedule| schedule.after(&running_server.start_time).next())
+            }),
+            restart_counts: self.restart_counts,
+            players: game_state.map(|game_state| game_state.players.iter().cloned().collect()),
+            update_available: running_server
+                .map(|running_server| {
+                    let latest_image_id = running_server
+                        .host
+                        .latest_image_id(&self.config.game_config.docker_image);
+                    running_server.image_id.is_some()
+                        && latest_image_id.is_some()
+                        && running_server.image_id != latest_image_id
+                })
+                .unwrap_or(false),
         }
-        self.state = ServerState::NotRunning;
     }
 }
 
 impl ServerCluster {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(
+        hosts: Vec<Arc<Host>>,
+        notifier: Notifier,
+        game_log_sender: UnboundedSender<GameLogEvent>,
+    ) -> Self {
+        ServerCluster {
+            hosts,
+            servers: Vec::new(),
+            rollout: None,
+            notifier,
+            game_log_sender,
+            stop_notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Notified whenever a server finishes stopping, so it can be picked up by polling straight away.
+    pub fn stop_notify(&self) -> Arc<Notify> {
+        self.stop_notify.clone()
+    }
+
+    /// The current time according to the runtime, which lets tests move the clock.
+    fn now(&self) -> DateTime<Utc> {
+        self.hosts[0].runtime.now()
+    }
+
+    pub fn status(&self) -> Vec<ServerStatus> {
+        self.servers.iter().map(Server::status).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Server> {
+        self.servers.iter().find(|server| server.id == name)
     }
 
     pub fn get_mut(&mut self, name: &str) -> Option<&mut Server> {
@@ -311,6 +1048,15 @@ impl ServerCluster {
                 Some(matching_server) => {
                     // Carry the state across from the old server
                     std::mem::swap(&mut new_server.state, &mut matching_server.state);
+                    std::mem::swap(
+                        &mut new_server.restart_backoff,
+                        &mut matching_server.restart_backoff,
+                    );
+                    new_server.restart_counts = matching_server.restart_counts;
+                    new_server.is_planned_restart = matching_server.is_planned_restart;
+                    new_server.failure_count = matching_server.failure_count;
+                    new_server.image_pull = matching_server.image_pull.take();
+                    new_server.placement_error = matching_server.placement_error.take();
 
                     if new_server.config != matching_server.config {
                         warn!("Server {} config has changed, this will only apply the next time the server is started", new_server.id);
@@ -323,7 +1069,7 @@ impl ServerCluster {
         let mut old_servers = new_servers;
         std::mem::swap(&mut old_servers, &mut self.servers);
         for mut old_server in old_servers {
-            if let ServerState::Running { .. } = &old_server.state {
+            if let ServerState::Running(_) | ServerState::Stopping(_) = &old_server.state {
                 warn!(
                     "Server {} is no longer in the config, use the \"stopold\" command to stop it",
                     old_server.id
@@ -335,20 +1081,110 @@ impl ServerCluster {
         }
     }
 
-    pub async fn stop_old(&mut self, docker: &Docker) {
+    /// Starts stopping the servers that are no longer in the config, which are forgotten once
+    /// they've stopped.
+    pub fn stop_old(&mut self) {
+        for server in self.servers.iter_mut().filter(|server| server.is_old) {
+            server.stop(&self.stop_notify);
+        }
+    }
+
+    /// Stops every server and waits for them all to finish stopping.
+    pub async fn stop_all(&mut self) {
         for server in &mut self.servers {
-            if server.is_old {
-                server.stop(docker).await;
-            }
+            server.stop(&self.stop_notify);
         }
+        let poll_time = self.now();
+        let notifier = &self.notifier;
+        let stop_futures = self
+            .servers
+            .iter_mut()
+            .map(|server| server.wait_for_stop(poll_time, notifier));
+        futures::future::join_all(stop_futures).await;
+    }
 
-        self.servers.retain(|server| !server.is_old);
+    pub async fn apply_log_retention(&self) {
+        for server in &self.servers {
+            LogPolicy::new(&server.id, &server.config.game_config)
+                .apply_retention()
+                .await;
+        }
     }
 
-    pub async fn stop_all(&mut self, docker: &Docker) {
+    pub fn restart_all(&mut self) {
         for server in &mut self.servers {
-            server.stop(docker).await;
+            server.restart(RestartReason::Manual, &self.stop_notify);
+        }
+    }
+
+    /// Starts restarting the named servers, which are started again once they've stopped.
+    pub fn restart(&mut self, server_ids: &[String]) {
+        self.log_unknown_servers(server_ids);
+        for server in self
+            .servers
+            .iter_mut()
+            .filter(|server| server_ids.contains(&server.id))
+        {
+            server.restart(RestartReason::Manual, &self.stop_notify);
+        }
+    }
+
+    /// Starts stopping the named servers and keeps them stopped, see [`Server::hold`].
+    pub fn hold(&mut self, server_ids: &[String]) {
+        self.log_unknown_servers(server_ids);
+        for server in self
+            .servers
+            .iter_mut()
+            .filter(|server| server_ids.contains(&server.id))
+        {
+            server.hold(&self.stop_notify);
+        }
+    }
+
+    fn log_unknown_servers(&self, server_ids: &[String]) {
+        for server_id in server_ids {
+            if self.get(server_id).is_none() {
+                info!("Unknown server {}", server_id);
+            }
+        }
+    }
+
+    /// Starts restarting the servers in order, or every server in the config if none are given.
+    pub fn start_rollout(
+        &mut self,
+        server_ids: Vec<String>,
+        concurrency: usize,
+        ready_timeout: chrono::Duration,
+    ) -> Result<(), String> {
+        if self.rollout.is_some() {
+            return Err("A rollout is already in progress".to_string());
+        }
+        if concurrency == 0 {
+            return Err("Rollout concurrency must be at least 1".to_string());
+        }
+        if let Some(server_id) = server_ids
+            .iter()
+            .find(|server_id| self.get(server_id).is_none())
+        {
+            return Err(format!("Unknown server {}", server_id));
         }
+
+        let server_ids = if server_ids.is_empty() {
+            self.servers
+                .iter()
+                .filter(|server| !server.is_old)
+                .map(|server| server.id.clone())
+                .collect()
+        } else {
+            server_ids
+        };
+        info!(
+            "Rolling out {} server(s), {} at a time",
+            server_ids.len(),
+            concurrency
+        );
+        self.rollout = Some(Rollout::new(server_ids, concurrency, ready_timeout));
+        Ok(())
     }
 
     pub fn serialize(&self) -> Vec<SerializedServer> {
@@ -356,13 +1192,17 @@ impl ServerCluster {
             .iter()
             .filter_map(|server| match &server.state {
                 ServerState::Running(RunningServer {
+                    host,
                     container_id,
                     game_port,
+                    auth_port,
                     ..
                 }) => Some(SerializedServer {
                     name: server.id.clone(),
+                    host: Some(host.name.clone()),
                     container_id: container_id.to_string(),
                     game_port: *game_port,
+                    auth_port: *auth_port,
                 }),
                 _ => None,
             })
@@ -372,9 +1212,20 @@ impl ServerCluster {
     pub async fn deserialize(
         &mut self,
         serialized_servers: Vec<SerializedServer>,
-        docker: &Docker,
+        instance_id: &str,
     ) {
         for serialized_server in serialized_servers {
+            let host = match &serialized_server.host {
+                Some(host_name) => self.hosts.iter().find(|host| &host.name == host_name),
+                None => self.hosts.first(),
+            };
+            let host = match host {
+                Some(host) => host.clone(),
+                None => {
+                    warn!("Server {} was running on host {}, which is no longer in the config, so won't be controlled by R2Wraith. It might still be running!", serialized_server.name, serialized_server.host.as_deref().unwrap_or_default());
+                    continue;
+                }
+            };
             let matching_server = match self.get_mut(&serialized_server.name) {
                 Some(server) => server,
                 None => {
@@ -383,9 +1234,11 @@ impl ServerCluster {
                 }
             };
 
-            let maybe_inspect = docker
-                .inspect_container(&serialized_server.container_id, None)
+            let maybe_inspect = host
+                .runtime
+                .inspect_container(&serialized_server.container_id)
                 .await
+                .inspect_err(record_docker_error)
                 .ok();
             let inspect = match maybe_inspect {
                 Some(inspect) if get_container_is_running(&inspect) => inspect,
@@ -409,19 +1262,220 @@ impl ServerCluster {
             };
 
             debug!(
-                "Restored {} with container {}",
-                matching_server.id, serialized_server.container_id
+                "Restored {} on {} with container {}",
+                matching_server.id, host.name, serialized_server.container_id
             );
             matching_server.state = ServerState::Running(RunningServer {
+                host,
                 container_id: serialized_server.container_id.clone(),
+                image_id: inspect.image.clone(),
                 game_port: serialized_server.game_port,
+                auth_port: serialized_server.auth_port,
+                start_time,
+                ready_time: Some(start_time),
+                game_state: None,
+                last_restart_warning_minutes: None,
+                is_waiting_to_restart: false,
+            });
+        }
+
+        // Pick up anything that wasn't in the restore file, e.g. if R2Wraith didn't exit cleanly
+        for host in self.hosts.clone() {
+            self.rediscover(instance_id, &host).await;
+        }
+    }
+
+    async fn rediscover(&mut self, instance_id: &str, host: &Arc<Host>) {
+        let instance_filter = format!("{}={}", INSTANCE_LABEL, instance_id);
+        let containers = match host.runtime.list_containers(&instance_filter).await {
+            Ok(containers) => containers,
+            Err(why) => {
+                record_docker_error(&why);
+                warn!(
+                    "Failed to list running containers on {}: {}",
+                    host.name, why
+                );
+                return;
+            }
+        };
+
+        for container in containers {
+            let (container_id, labels) = match (container.id, container.labels) {
+                (Some(container_id), Some(labels)) => (container_id, labels),
+                _ => continue,
+            };
+            let server_id = match labels.get(SERVER_LABEL) {
+                Some(server_id) => server_id,
+                None => continue,
+            };
+            let server = match self.get_mut(server_id) {
+                Some(server) => server,
+                None => {
+                    warn!("Server {} is no longer in the config, so won't be controlled by R2Wraith. It might still be running!", server_id);
+                    continue;
+                }
+            };
+
+            if let ServerState::Running(running_server) = &server.state {
+                if running_server.host.name != host.name
+                    || running_server.container_id != container_id
+                {
+                    warn!(
+                        "Server {} has another container {} running on {}, it won't be controlled by R2Wraith",
+                        server.id, container_id, host.name
+                    );
+                }
+                continue;
+            }
+
+            let game_port = match labels
+                .get(GAME_PORT_LABEL)
+                .and_then(|game_port| game_port.parse().ok())
+            {
+                Some(game_port) => game_port,
+                None => {
+                    warn!("Server {} does not have a valid game port label", server.id);
+                    continue;
+                }
+            };
+            let auth_port = labels
+                .get(AUTH_PORT_LABEL)
+                .and_then(|auth_port| auth_port.parse().ok());
+            let start_time = match container
+                .created
+                .and_then(|created| Utc.timestamp_opt(created, 0).single())
+            {
+                Some(start_time) => start_time,
+                None => {
+                    warn!("Server {} does not have a valid created time", server.id);
+                    continue;
+                }
+            };
+
+            if labels.get(CONFIG_HASH_LABEL) != Some(&server.config_hash(game_port, auth_port)) {
+                warn!("Server {} config has changed, this will only apply the next time the server is started", server.id);
+            }
+
+            debug!(
+                "Rediscovered {} on {} with container {}",
+                server.id, host.name, container_id
+            );
+            server.state = ServerState::Running(RunningServer {
+                host: host.clone(),
+                container_id,
+                image_id: container.image_id,
+                game_port,
+                auth_port,
                 start_time,
+                ready_time: Some(start_time),
+                game_state: None,
+                last_restart_warning_minutes: None,
+                is_waiting_to_restart: false,
             });
         }
     }
 
-    pub async fn poll(&mut self, config: &Config, docker: &Docker) -> PollStatus {
-        let poll_time = Utc::now();
+    pub fn handle_container_event(&mut self, event: &ContainerEvent) -> bool {
+        let now = self.now();
+        let notifier = &self.notifier;
+        let server = self.servers.iter_mut().find(|server| match &server.state {
+            ServerState::Running(running_server) => {
+                running_server.host.name == event.host_name
+                    && running_server.container_id == event.container_id
+            }
+            _ => false,
+        });
+        match server {
+            Some(server) => {
+                let message = format!(
+                    "Server {} appears to have stopped (container {} on {} {})",
+                    server.id, event.container_id, event.host_name, event.kind
+                );
+                server.record_crash(now, message, notifier);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn handle_game_log_event(&mut self, event: &GameLogEvent) {
+        // Ignore anything left over from a container that has since been replaced
+        let server = self.servers.iter_mut().find(|server| {
+            server.id == event.server_id && server.container_id() == Some(&event.container_id)
+        });
+        let (server_id, running_server) = match server {
+            Some(Server {
+                id,
+                state: ServerState::Running(running_server),
+                ..
+            }) => (id, running_server),
+            _ => return,
+        };
+        if event.kind == GameLogEventKind::Ready {
+            if running_server.ready_time.is_none() {
+                info!("Server {} is ready", server_id);
+                running_server.ready_time = Some(running_server.host.runtime.now());
+            }
+            return;
+        }
+        let game_state = match &mut running_server.game_state {
+            Some(game_state) => game_state,
+            None => return,
+        };
+
+        match &event.kind {
+            GameLogEventKind::PlayerConnected(player) => {
+                info!("{} joined {}", player, server_id);
+                game_state.players.insert(player.clone());
+            }
+            GameLogEventKind::PlayerDisconnected(player) => {
+                info!("{} left {}", player, server_id);
+                game_state.players.remove(player);
+            }
+            GameLogEventKind::MatchStarted => {
+                info!("Match started on {}", server_id);
+                game_state.is_match_in_progress = true;
+            }
+            GameLogEventKind::MatchEnded => {
+                info!("Match ended on {}", server_id);
+                game_state.is_match_in_progress = false;
+            }
+            GameLogEventKind::Crashed(message) => {
+                error!("Server {} reported a crash: {}", server_id, message);
+            }
+            GameLogEventKind::Ready => {}
+        }
+    }
+
+    pub async fn poll(&mut self, config: &Config) -> PollStatus {
+        let instance_id = config.instance_id.as_str();
+        let poll_time = self.now();
+        for server in &mut self.servers {
+            server.poll_stop(poll_time, &self.notifier);
+        }
+        // Servers that are no longer in the config are forgotten once they've stopped
+        self.servers.retain(|server| {
+            !server.is_old
+                || matches!(
+                    server.state,
+                    ServerState::Running(_) | ServerState::Stopping(_)
+                )
+        });
+
+        if let Some(rollout) = &mut self.rollout {
+            if !rollout.poll(
+                &mut self.servers,
+                poll_time,
+                &self.notifier,
+                &self.stop_notify,
+            ) {
+                self.rollout = None;
+            }
+        }
+
+        let notifier = &self.notifier;
+        let stop_notify = &self.stop_notify;
+        let game_log_sender = &self.game_log_sender;
         let restart_servers_futures =
             self.servers
                 .iter_mut()
@@ -429,13 +1483,20 @@ impl ServerCluster {
                 .map(|(server_index, server)| async move {
                     let running_server = match &server.state {
                         ServerState::Running(running_server) => running_server,
-                        ServerState::NotRunning => return Some(server_index),
+                        ServerState::NotRunning if server.is_pulling_image() => return None,
+                        ServerState::NotRunning if server.restart_backoff.can_start(poll_time) => {
+                            return Some(server_index)
+                        }
+                        _ => return None,
                     };
 
                     let has_stopped = matches!(
-                        docker
-                            .inspect_container(&running_server.container_id, None)
+                        running_server
+                            .host
+                            .runtime
+                            .inspect_container(&running_server.container_id)
                             .await
+                            .inspect_err(record_docker_error)
                             .ok(),
                         None | Some(ContainerInspectResponse {
                             state: None
@@ -447,99 +1508,119 @@ impl ServerCluster {
                         })
                     );
                     if has_stopped {
-                        warn!(
-                            "Server {} appears to have stopped (container {} is no longer running)",
-                            server.id, running_server.container_id
+                        let message = format!(
+                            "Server {} appears to have stopped (container {} is no longer running on {})",
+                            server.id, running_server.container_id, running_server.host.name
                         );
-                        server.state = ServerState::NotRunning;
-                        return Some(server_index);
-                    }
-
-                    if let Some(schedule) = &server.config.game_config.restart_schedule {
-                        if let Some(next_restart_time) =
-                            schedule.after(&running_server.start_time).next()
-                        {
-                            if next_restart_time < poll_time {
-                                warn!("Server {} has passed a scheduled restart", server.id);
-                                server.stop(docker).await;
-                                if let ServerState::NotRunning = server.state {
-                                    return Some(server_index);
-                                }
+                        server.record_crash(poll_time, message, notifier);
+                        return match server.state {
+                            ServerState::NotRunning
+                                if server.restart_backoff.can_start(poll_time) =>
+                            {
+                                Some(server_index)
                             }
-                        }
+                            _ => None,
+                        };
                     }
 
-                    return None;
+                    // Both of these stop the server in the background, and it's started again on a
+                    // later poll once the stop has finished
+                    server.poll_readiness(poll_time, stop_notify).await;
+                    server
+                        .poll_restart_schedule(poll_time, notifier, stop_notify)
+                        .await;
+                    None
                 });
 
         let restart_server_indices = futures::future::join_all(restart_servers_futures).await;
-        let mut game_ports_in_use: HashSet<_> = self
-            .servers
+        let host_limits: Vec<_> = self
+            .hosts
             .iter()
-            .filter_map(|server| match &server.state {
-                ServerState::NotRunning => None,
-                ServerState::Running(RunningServer { game_port, .. }) => Some(*game_port),
-            })
+            .map(|host| (host.name.as_str(), &host.limits))
             .collect();
-
-        struct RestartServerDetails {
-            game_port: u16,
+        let mut host_usages: Vec<_> = self.hosts.iter().map(|_| HostUsage::default()).collect();
+        for server in &self.servers {
+            // Stopping servers hold onto their ports until their containers are gone
+            let running_server = match &server.state {
+                ServerState::Running(running_server)
+                | ServerState::Stopping(StoppingServer { running_server, .. }) => running_server,
+                _ => continue,
+            };
+            if let Some(host_index) = self
+                .hosts
+                .iter()
+                .position(|host| host.name == running_server.host.name)
+            {
+                host_usages[host_index].add(running_server.game_port, running_server.auth_port);
+            }
         }
-        let restart_server_details = restart_server_indices
+
+        let restart_server_placements = restart_server_indices
             .into_iter()
-            .filter_map(|index| index)
+            .flatten()
             .filter_map(|server_index| {
-                let server = &self.servers[server_index];
-
-                // Allocate free ports
-                let game_port = match server.config.game_port {
-                    Some(port) if !game_ports_in_use.contains(&port) => port,
-                    Some(used_port) => {
-                        error!("Specified game port {} is not free", used_port);
-                        return None;
+                let server = &mut self.servers[server_index];
+                match place_server(server, &host_limits, &host_usages) {
+                    Ok(placement) => {
+                        // Ensure other servers can't use this space
+                        host_usages[placement.host_index]
+                            .add(placement.game_port, placement.auth_port);
+                        server.placement_error = None;
+                        Some((server_index, placement))
                     }
-                    None => match config
-                        .game_ports
-                        .clone()
-                        .into_iter()
-                        .find(|port| !game_ports_in_use.contains(port))
-                    {
-                        Some(port) => port,
-                        None => {
-                            error!(
-                                "No game ports between {} and {} are free",
-                                config.game_ports.start(),
-                                config.game_ports.end()
-                            );
-                            return None;
+                    Err(why) => {
+                        // This is tried again every poll, so only report it when it first happens
+                        if server.placement_error.as_ref() != Some(&why) {
+                            match &why {
+                                PlacementError::HostsFull(message) => debug!("{}", message),
+                                PlacementError::Unplaceable(message) => {
+                                    error!("{}", message);
+                                    notifier.notify(
+                                        NotificationEvent::PortsExhausted,
+                                        server,
+                                        message.clone(),
+                                    );
+                                }
+                            }
+                            server.placement_error = Some(why);
                         }
-                    },
-                };
-
-                // Ensure other servers can't use these ports
-                game_ports_in_use.insert(game_port);
-
-                Some((server_index, RestartServerDetails { game_port }))
+                        None
+                    }
+                }
             })
             .collect::<HashMap<_, _>>();
 
-        if restart_server_details.is_empty() {
+        if restart_server_placements.is_empty() {
             return PollStatus::NoWork;
         }
 
-        let restart_server_details = &restart_server_details;
+        let hosts = &self.hosts;
+        let restart_server_placements = &restart_server_placements;
         let start_server_futures =
             self.servers
                 .iter_mut()
                 .enumerate()
                 .map(|(server_index, server)| async move {
-                    let details = match restart_server_details.get(&server_index) {
-                        Some(details) => details,
+                    let placement = match restart_server_placements.get(&server_index) {
+                        Some(placement) => placement,
                         None => return,
                     };
 
-                    if let Err(why) = server.start(details.game_port, docker).await {
-                        error!("Could not start {}: {}", server.id, why);
+                    let host = &hosts[placement.host_index];
+                    if let Err(why) = server
+                        .start(
+                            host,
+                            placement.game_port,
+                            placement.auth_port,
+                            instance_id,
+                            game_log_sender,
+                        )
+                        .await
+                    {
+                        let message = format!("Could not start {}: {}", server.id, why);
+                        error!("{}", message);
+                        notifier.notify(NotificationEvent::StartFailed, server, message);
+                        server.record_failure(host.runtime.now(), notifier);
                     }
                 });
         futures::future::join_all(start_server_futures).await;
@@ -547,6 +1628,294 @@ impl ServerCluster {
     }
 }
 
+/// What's already running on a host.
+#[derive(Debug, Default)]
+pub struct HostUsage {
+    server_count: usize,
+    game_ports: HashSet<u16>,
+    auth_ports: HashSet<u16>,
+}
+
+impl HostUsage {
+    pub fn add(&mut self, game_port: u16, auth_port: Option<u16>) {
+        self.server_count += 1;
+        self.game_ports.insert(game_port);
+        if let Some(auth_port) = auth_port {
+            self.auth_ports.insert(auth_port);
+        }
+    }
+}
+
+/// Where a server will be started, as an index into the hosts it was placed from.
+pub struct Placement {
+    pub host_index: usize,
+    pub game_port: u16,
+    pub auth_port: Option<u16>,
+}
+
+/// Picks a host and ports for the server, given what's already on each host. Servers that aren't
+/// pinned to a host go on the one with the most room left, or the first in the config if there's
+/// a tie.
+pub fn place_server(
+    server: &Server,
+    hosts: &[(&str, &HostLimits)],
+    host_usages: &[HostUsage],
+) -> Result<Placement, PlacementError> {
+    let candidate_host_indices: Vec<_> = match &server.config.host {
+        Some(host_name) => match hosts.iter().position(|(name, _)| name == host_name) {
+            Some(host_index) => vec![host_index],
+            None => {
+                return Err(PlacementError::Unplaceable(format!(
+                    "Server {} can't start, its host {} isn't in the config",
+                    server.id, host_name
+                )))
+            }
+        },
+        None => (0..hosts.len()).collect(),
+    };
+
+    let mut best_placement: Option<(usize, Placement)> = None;
+    let mut reasons = Vec::new();
+    for host_index in candidate_host_indices {
+        let (host_name, host_limits) = hosts[host_index];
+        match place_server_on_host(server, host_name, host_limits, &host_usages[host_index]) {
+            Ok((free_slots, game_port, auth_port)) => {
+                let is_better = best_placement
+                    .as_ref()
+                    .map(|(best_free_slots, _)| free_slots > *best_free_slots)
+                    .unwrap_or(true);
+                if is_better {
+                    best_placement = Some((
+                        free_slots,
+                        Placement {
+                            host_index,
+                            game_port,
+                            auth_port,
+                        },
+                    ));
+                }
+            }
+            Err(reason) => reasons.push(reason),
+        }
+    }
+
+    if let Some((_, placement)) = best_placement {
+        return Ok(placement);
+    }
+
+    // Only waiting for room if there would be room on some host once servers stop
+    let are_hosts_full = reasons
+        .iter()
+        .all(|reason| matches!(reason, PlacementError::HostsFull(_)));
+    let reasons: Vec<_> = reasons.iter().map(|reason| reason.to_string()).collect();
+    let message = if reasons.len() == 1 {
+        format!("Server {} can't start, {}", server.id, reasons[0])
+    } else {
+        format!(
+            "Server {} can't start on any host: {}",
+            server.id,
+            reasons.join("; ")
+        )
+    };
+    if are_hosts_full {
+        Err(PlacementError::HostsFull(message))
+    } else {
+        Err(PlacementError::Unplaceable(message))
+    }
+}
+
+/// Allocates free ports for the server on the host, returning how many more servers the host
+/// could take afterwards along with the ports.
+fn place_server_on_host(
+    server: &Server,
+    host_name: &str,
+    host_limits: &HostLimits,
+    host_usage: &HostUsage,
+) -> Result<(usize, u16, Option<u16>), PlacementError> {
+    if let Some(capacity) = host_limits.capacity {
+        if host_usage.server_count >= capacity {
+            return Err(PlacementError::HostsFull(format!(
+                "host {} is full with {} server(s)",
+                host_name, host_usage.server_count
+            )));
+        }
+    }
+
+    let game_port = match server.config.game_port {
+        Some(port) if !host_usage.game_ports.contains(&port) => port,
+        Some(used_port) => {
+            return Err(PlacementError::Unplaceable(format!(
+                "its game port {} is not free on {}",
+                used_port, host_name
+            )))
+        }
+        None => match host_limits
+            .game_ports
+            .clone()
+            .find(|port| !host_usage.game_ports.contains(port))
+        {
+            Some(port) => port,
+            None => {
+                return Err(PlacementError::Unplaceable(format!(
+                    "no game ports between {} and {} are free on {}",
+                    host_limits.game_ports.start(),
+                    host_limits.game_ports.end(),
+                    host_name
+                )))
+            }
+        },
+    };
+
+    // The auth server is only needed by older images, so is only allocated if configured
+    let auth_port = match (server.config.auth_port, &host_limits.auth_ports) {
+        (Some(port), _) if !host_usage.auth_ports.contains(&port) => Some(port),
+        (Some(used_port), _) => {
+            return Err(PlacementError::Unplaceable(format!(
+                "its auth port {} is not free on {}",
+                used_port, host_name
+            )))
+        }
+        (None, Some(auth_ports)) => match auth_ports
+            .clone()
+            .find(|port| !host_usage.auth_ports.contains(port))
+        {
+            Some(port) => Some(port),
+            None => {
+                return Err(PlacementError::Unplaceable(format!(
+                    "no auth ports between {} and {} are free on {}",
+                    auth_ports.start(),
+                    auth_ports.end(),
+                    host_name
+                )))
+            }
+        },
+        (None, None) => None,
+    };
+
+    // Count the room left after this server, which is limited by the game ports and capacity
+    let free_game_ports = host_limits
+        .game_ports
+        .clone()
+        .filter(|port| *port != game_port && !host_usage.game_ports.contains(port))
+        .count();
+    let free_slots = match host_limits.capacity {
+        Some(capacity) => free_game_ports.min(capacity - host_usage.server_count - 1),
+        None => free_game_ports,
+    };
+    Ok((free_slots, game_port, auth_port))
+}
+
+/// Warns players through the console that the server will restart in the given number of minutes.
+async fn send_restart_warning(
+    server_id: &str,
+    running_server: &RunningServer,
+    game_config: &FilledGameConfig,
+    minutes: u32,
+) {
+    let command = game_config
+        .restart_warning_command
+        .replace("{minutes}", &minutes.to_string());
+    if let Err(why) = write_console_command(
+        running_server.host.runtime.as_ref(),
+        &running_server.container_id,
+        &command,
+    )
+    .await
+    {
+        warn!("Failed to send restart warning to {}: {}", server_id, why);
+    }
+}
+
+/// Container names are unique on a host, so they include the instance for more than one R2Wraith
+/// to share a host.
+pub fn container_name(instance_id: &str, server_id: &str) -> String {
+    format!("{}{}-{}", CONTAINER_NAME_PREFIX, instance_id, server_id)
+}
+
+/// Asks the runtime to stop the container, and kills and removes it if it doesn't go away in time.
+async fn stop_container(
+    server_id: &str,
+    container_id: &str,
+    stop_timeout: Duration,
+    runtime: &dyn ContainerRuntime,
+) -> Result<(), StopServerError> {
+    let deadline = Instant::now() + stop_timeout + STOP_GRACE_PERIOD;
+    let stop_result =
+        timeout_at(deadline, runtime.stop_container(container_id, stop_timeout)).await;
+    if let Ok(Err(why)) = &stop_result {
+        record_docker_error(why);
+        if !is_not_found_error(why) {
+            warn!("Failed to stop {}: {}", server_id, why);
+        }
+    }
+    if wait_for_removal(container_id, deadline, runtime).await {
+        return Ok(());
+    }
+
+    warn!("Server {} didn't stop in time, killing it", server_id);
+    let kill_deadline = Instant::now() + KILL_TIMEOUT;
+    match timeout_at(kill_deadline, runtime.kill_container(container_id)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(why)) => {
+            record_docker_error(&why);
+            warn!("Failed to kill {}: {}", server_id, why);
+        }
+        Err(_) => warn!("Timed out killing {}", server_id),
+    }
+    if wait_for_removal(container_id, kill_deadline, runtime).await {
+        return Ok(());
+    }
+
+    // The container should have been auto-removed by now, so try removing it ourselves
+    let remove_deadline = Instant::now() + KILL_TIMEOUT;
+    match timeout_at(remove_deadline, runtime.remove_container(container_id)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(why)) if is_not_found_error(&why) => return Ok(()),
+        Ok(Err(why)) => {
+            record_docker_error(&why);
+            return Err(StopServerError::ContainerNotRemoved(why));
+        }
+        Err(_) => return Err(StopServerError::TimedOut),
+    }
+    if wait_for_removal(container_id, remove_deadline, runtime).await {
+        Ok(())
+    } else {
+        Err(StopServerError::TimedOut)
+    }
+}
+
+/// Waits until the container no longer exists, returning false if it's still there at the deadline.
+async fn wait_for_removal(
+    container_id: &str,
+    deadline: Instant,
+    runtime: &dyn ContainerRuntime,
+) -> bool {
+    loop {
+        match timeout_at(deadline, runtime.inspect_container(container_id)).await {
+            Ok(Err(why)) if is_not_found_error(&why) => return true,
+            Ok(Err(why)) => record_docker_error(&why),
+            Ok(Ok(_)) => {}
+            Err(_) => return false,
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        debug!("Waiting for container {} to stop", container_id);
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+fn is_not_found_error(error: &bollard::errors::Error) -> bool {
+    matches!(
+        error,
+        bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
 fn get_container_is_running(inspect: &ContainerInspectResponse) -> bool {
     inspect
         .state
@@ -562,3 +1931,6 @@ fn get_container_created(details: &ContainerInspectResponse) -> Option<DateTime<
         .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
         .map(|time| time.with_timezone(&Utc))
 }
+
+#[cfg(test)]
+mod tests;
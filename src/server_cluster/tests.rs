@@ -0,0 +1,1042 @@
+use super::*;
+use crate::get_server_list_from_config;
+use crate::runtime::fake::{FakeContainer, FakeRuntime, StopBehaviour};
+use std::path::PathBuf;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// Holds a cluster running against a fake runtime for each host. Each test gets its own config
+/// directory, so server logs don't end up in the repository.
+struct TestCluster {
+    config: Config,
+    config_dir: PathBuf,
+    hosts: Vec<Arc<Host>>,
+    /// The runtime of the first host, which is the only one unless the config has `[[hosts]]`.
+    runtime: Arc<FakeRuntime>,
+    host_runtimes: HashMap<String, Arc<FakeRuntime>>,
+    cluster: ServerCluster,
+    game_log_receiver: UnboundedReceiver<GameLogEvent>,
+}
+
+impl TestCluster {
+    fn new(test_name: &str, config_text: &str) -> Self {
+        let config_dir = std::env::temp_dir().join(format!(
+            "r2wraith-test-{}-{}",
+            std::process::id(),
+            test_name
+        ));
+        std::fs::create_dir_all(&config_dir).unwrap();
+
+        let config: Config = toml::from_str(config_text).unwrap();
+        let mut hosts = Vec::new();
+        let mut host_runtimes = HashMap::new();
+        for host_config in config.host_configs() {
+            let runtime = Arc::new(FakeRuntime::new(Utc.ymd(2026, 1, 1).and_hms(0, 0, 0)));
+            runtime.publish_image("northstar-dedicated", "sha256:1");
+            runtime.load_image("northstar-dedicated", "sha256:1");
+            host_runtimes.insert(host_config.name.clone(), runtime.clone());
+            hosts.push(Arc::new(Host::new(&host_config, &config, runtime)));
+        }
+
+        let (game_log_sender, game_log_receiver) = unbounded_channel();
+        let mut cluster = ServerCluster::new(hosts.clone(), Notifier::default(), game_log_sender);
+        cluster.load_servers(get_server_list_from_config(&config, &config_dir));
+        TestCluster {
+            config,
+            config_dir,
+            runtime: host_runtimes[&hosts[0].name].clone(),
+            hosts,
+            host_runtimes,
+            cluster,
+            game_log_receiver,
+        }
+    }
+
+    /// Starts another cluster on the same runtime and config, as if R2Wraith had been restarted.
+    fn restart_wraith(&mut self) {
+        let (game_log_sender, game_log_receiver) = unbounded_channel();
+        self.cluster = ServerCluster::new(self.hosts.clone(), Notifier::default(), game_log_sender);
+        self.cluster.load_servers(self.servers());
+        self.game_log_receiver = game_log_receiver;
+    }
+
+    fn servers(&self) -> Vec<Server> {
+        get_server_list_from_config(&self.config, &self.config_dir)
+    }
+
+    /// Lets the log pipes catch up and hands their events to the cluster, then polls.
+    async fn poll(&mut self) -> PollStatus {
+        sleep(Duration::from_millis(10)).await;
+        while let Ok(event) = self.game_log_receiver.try_recv() {
+            self.cluster.handle_game_log_event(&event);
+        }
+        self.cluster.poll(&self.config).await
+    }
+
+    fn host_runtime(&self, host_name: &str) -> &FakeRuntime {
+        &self.host_runtimes[host_name]
+    }
+
+    fn container(&self, server_id: &str) -> FakeContainer {
+        self.runtime
+            .running_container(&container_name(&self.config.instance_id, server_id))
+            .unwrap_or_else(|| panic!("{} should be running", server_id))
+    }
+
+    fn status(&self, server_id: &str) -> ServerStatus {
+        self.cluster.get(server_id).unwrap().status()
+    }
+}
+
+impl Drop for TestCluster {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.config_dir);
+    }
+}
+
+fn label<'a>(container: &'a FakeContainer, key: &str) -> Option<&'a str> {
+    container
+        .config
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(key))
+        .map(String::as_str)
+}
+
+fn has_env_var(container: &FakeContainer, env_var: &str) -> bool {
+    container
+        .config
+        .env
+        .iter()
+        .flatten()
+        .any(|container_env_var| container_env_var == env_var)
+}
+
+/// Logs the line matched by `ready-log-pattern = "Test server ready"`.
+fn log_ready(test: &TestCluster, server_id: &str) {
+    let container = test.container(server_id);
+    test.runtime
+        .push_log(&container.id, "[info] Test server ready");
+}
+
+const TWO_SERVERS: &str = r#"
+game-ports = { start = 37015, end = 37020 }
+
+[defaults]
+docker-image = "northstar-dedicated"
+game-dir = "/titanfall"
+
+[servers.alpha]
+name = "Alpha"
+
+[servers.bravo]
+name = "Bravo"
+"#;
+
+#[tokio::test(start_paused = true)]
+async fn poll_starts_servers_on_free_ports() {
+    let mut test = TestCluster::new(
+        "poll_starts_servers_on_free_ports",
+        r#"
+        game-ports = { start = 37015, end = 37016 }
+        auth-ports = { start = 8081, end = 8090 }
+
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+
+        [servers.alpha]
+        name = "Alpha"
+
+        [servers.bravo]
+        name = "Bravo"
+        auth-port = 8085
+
+        [servers.charlie]
+        name = "Charlie"
+        "#,
+    );
+
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+
+    let alpha = test.container("alpha");
+    assert_eq!(label(&alpha, SERVER_LABEL), Some("alpha"));
+    assert_eq!(label(&alpha, INSTANCE_LABEL), Some("default"));
+    assert_eq!(label(&alpha, GAME_PORT_LABEL), Some("37015"));
+    assert_eq!(label(&alpha, AUTH_PORT_LABEL), Some("8081"));
+    assert!(has_env_var(&alpha, "NS_PORT=37015"));
+    assert!(has_env_var(&alpha, "NS_PORT_AUTH=8081"));
+
+    let bravo = test.container("bravo");
+    assert_eq!(label(&bravo, GAME_PORT_LABEL), Some("37016"));
+    assert_eq!(label(&bravo, AUTH_PORT_LABEL), Some("8085"));
+
+    // Both game ports are taken, so there's nowhere for the third server to go
+    assert_eq!(test.status("charlie").state, ServerStateKind::NotRunning);
+    assert_eq!(test.runtime.containers().len(), 2);
+
+    // Nothing changes until a port is freed up
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    test.cluster.hold(&["alpha".to_string()]);
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    let charlie = test.container("charlie");
+    assert_eq!(label(&charlie, GAME_PORT_LABEL), Some("37015"));
+    assert_eq!(label(&charlie, AUTH_PORT_LABEL), Some("8081"));
+    assert_eq!(test.status("alpha").state, ServerStateKind::Stopped);
+}
+
+#[tokio::test(start_paused = true)]
+async fn crashed_server_is_restarted_after_backoff() {
+    let mut test = TestCluster::new("crashed_server_is_restarted_after_backoff", TWO_SERVERS);
+    test.poll().await;
+    let first_container = test.container("alpha");
+
+    test.runtime.crash(&first_container.id);
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    let status = test.status("alpha");
+    assert_eq!(status.state, ServerStateKind::NotRunning);
+    assert_eq!(status.restart_counts.crash, 1);
+
+    // The first retry is after the initial backoff of 2 seconds
+    sleep(Duration::from_secs(1)).await;
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    sleep(Duration::from_secs(1)).await;
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+
+    let second_container = test.container("alpha");
+    assert_ne!(first_container.id, second_container.id);
+    assert_eq!(label(&second_container, GAME_PORT_LABEL), Some("37015"));
+    assert_eq!(test.status("bravo").restart_counts.total(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn crash_looping_server_waits_to_be_started() {
+    let mut test = TestCluster::new(
+        "crash_looping_server_waits_to_be_started",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        restart-backoff-initial-seconds = 0.0
+        restart-backoff-failure-limit = 3
+
+        [servers.alpha]
+        name = "Alpha"
+        "#,
+    );
+
+    for _ in 0..3 {
+        test.poll().await;
+        let container = test.container("alpha");
+        test.runtime.crash(&container.id);
+    }
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    assert_eq!(test.status("alpha").state, ServerStateKind::CrashLooping);
+    assert_eq!(test.status("alpha").restart_counts.crash, 3);
+
+    assert!(test.cluster.get_mut("alpha").unwrap().resume());
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    assert_eq!(test.status("alpha").state, ServerStateKind::Running);
+}
+
+#[tokio::test(start_paused = true)]
+async fn server_is_ready_once_its_log_pattern_matches() {
+    let mut test = TestCluster::new(
+        "server_is_ready_once_its_log_pattern_matches",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+
+        [servers.alpha]
+        name = "Alpha"
+        ready-log-pattern = "Test server ready"
+
+        [servers.bravo]
+        name = "Bravo"
+
+        [servers.charlie]
+        name = "Charlie"
+        ready-check = "log"
+        "#,
+    );
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Starting);
+
+    // Without a pattern there's nothing to wait for, even when the log check is asked for
+    assert_eq!(test.status("bravo").state, ServerStateKind::Running);
+    assert_eq!(test.status("charlie").state, ServerStateKind::Running);
+
+    let alpha = test.container("alpha");
+    test.runtime.push_log(
+        &alpha.id,
+        r#"[00:00:02] [info] Client "bob" connected (1.2.3.4:1)"#,
+    );
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Starting);
+
+    log_ready(&test, "alpha");
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Running);
+    assert!(test.cluster.get("alpha").unwrap().is_ready());
+}
+
+#[tokio::test(start_paused = true)]
+async fn server_that_isnt_ready_in_time_is_a_failed_start() {
+    let mut test = TestCluster::new(
+        "server_that_isnt_ready_in_time_is_a_failed_start",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        start-timeout-seconds = 60
+        ready-log-pattern = "Test server ready"
+        restart-backoff-initial-seconds = 10.0
+        restart-backoff-failure-limit = 2
+
+        [servers.alpha]
+        name = "Alpha"
+        "#,
+    );
+    test.poll().await;
+    let first_container = test.container("alpha");
+
+    sleep(Duration::from_secs(61)).await;
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Stopping);
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    assert_eq!(test.status("alpha").state, ServerStateKind::NotRunning);
+    assert!(test
+        .runtime
+        .running_container(&format!("{}alpha", CONTAINER_NAME_PREFIX))
+        .is_none());
+    assert_eq!(test.cluster.get("alpha").unwrap().failure_count(), 1);
+
+    sleep(Duration::from_secs(10)).await;
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    assert_ne!(test.container("alpha").id, first_container.id);
+    assert_eq!(test.status("alpha").state, ServerStateKind::Starting);
+
+    sleep(Duration::from_secs(61)).await;
+    test.poll().await;
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::CrashLooping);
+    assert_eq!(test.status("alpha").restart_counts.crash, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn scheduled_restart_warns_players_first() {
+    let mut test = TestCluster::new(
+        "scheduled_restart_warns_players_first",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        restart-schedule = "0 0 1 * * * *"
+        restart-warning-minutes = [5, 1]
+
+        [servers.alpha]
+        name = "Alpha"
+        "#,
+    );
+    test.poll().await;
+    let first_container = test.container("alpha");
+
+    sleep(Duration::from_secs(55 * 60 + 30)).await;
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    sleep(Duration::from_secs(60)).await;
+    test.poll().await;
+    sleep(Duration::from_secs(3 * 60)).await;
+    test.poll().await;
+    assert_eq!(
+        test.container("alpha").console_lines,
+        vec![
+            "say Server will restart in 5 minute(s)",
+            "say Server will restart in 1 minute(s)",
+        ]
+    );
+
+    sleep(Duration::from_secs(60)).await;
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Stopping);
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    let second_container = test.container("alpha");
+    assert_ne!(first_container.id, second_container.id);
+    assert_eq!(test.status("alpha").restart_counts.schedule, 1);
+    assert_eq!(
+        test.status("alpha").next_restart_time,
+        Some(Utc.ymd(2026, 1, 2).and_hms(1, 0, 0))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn when_empty_restart_waits_for_players_to_leave() {
+    let mut test = TestCluster::new(
+        "when_empty_restart_waits_for_players_to_leave",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        restart-schedule = "0 0 1 * * * *"
+        restart-policy = "when-empty"
+        restart-warning-minutes = []
+
+        [servers.alpha]
+        name = "Alpha"
+        "#,
+    );
+    test.poll().await;
+    let first_container = test.container("alpha");
+    test.runtime.push_log(
+        &first_container.id,
+        r#"[00:00:02] [info] Client "bob" connected (1.2.3.4:1)"#,
+    );
+
+    // The restart is at 01:00, but the server isn't empty until 01:10
+    sleep(Duration::from_secs(70 * 60)).await;
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    assert_eq!(test.status("alpha").players, Some(vec!["bob".to_string()]));
+
+    test.runtime.push_log(
+        &first_container.id,
+        "[01:10:00] [info] Dropped bob from server (Disconnect by user.)",
+    );
+    test.poll().await;
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    assert_ne!(test.container("alpha").id, first_container.id);
+    assert_eq!(test.status("alpha").restart_counts.schedule, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn after_match_restart_without_match_patterns_waits_the_full_time() {
+    let mut test = TestCluster::new(
+        "after_match_restart_without_match_patterns_waits_the_full_time",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        restart-schedule = "0 0 1 * * * *"
+        restart-policy = "after-match"
+        restart-max-wait-seconds = 600
+        restart-warning-minutes = []
+
+        [servers.alpha]
+        name = "Alpha"
+        "#,
+    );
+    test.poll().await;
+    let first_container = test.container("alpha");
+
+    // There's no telling whether a match is in progress, so it waits until the max wait
+    sleep(Duration::from_secs(61 * 60)).await;
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Running);
+
+    sleep(Duration::from_secs(10 * 60)).await;
+    test.poll().await;
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    assert_ne!(test.container("alpha").id, first_container.id);
+}
+
+#[tokio::test(start_paused = true)]
+async fn after_match_restart_warns_before_the_scheduled_time() {
+    let mut test = TestCluster::new(
+        "after_match_restart_warns_before_the_scheduled_time",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        restart-schedule = "0 0 1 * * * *"
+        restart-policy = "after-match"
+        match-start-log-pattern = "\\[MyMatchMod\\] match started"
+        match-end-log-pattern = "\\[MyMatchMod\\] match ended"
+        restart-warning-minutes = [5]
+
+        [servers.alpha]
+        name = "Alpha"
+        "#,
+    );
+    test.poll().await;
+    let first_container = test.container("alpha");
+    test.runtime
+        .push_log(&first_container.id, "[00:00:05] [MyMatchMod] match started");
+
+    // Warnings count down to the scheduled time, not the end of the max wait
+    sleep(Duration::from_secs(55 * 60 + 30)).await;
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    sleep(Duration::from_secs(20 * 60)).await;
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    assert_eq!(
+        test.container("alpha").console_lines,
+        vec!["say Server will restart in 5 minute(s)"]
+    );
+
+    // Players get one last warning when the match ends and the server is stopped
+    test.runtime.set_stop_behaviour(
+        "r2wraith-default-alpha",
+        StopBehaviour::Slow(Duration::from_secs(5)),
+    );
+    test.runtime
+        .push_log(&first_container.id, "[01:15:30] [MyMatchMod] match ended");
+    test.poll().await;
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Stopping);
+    assert_eq!(
+        test.container("alpha").console_lines,
+        vec![
+            "say Server will restart in 5 minute(s)",
+            "say Server will restart in 0 minute(s)",
+        ]
+    );
+
+    sleep(Duration::from_secs(5)).await;
+    assert!(matches!(test.poll().await, PollStatus::DidWork));
+    assert_ne!(test.container("alpha").id, first_container.id);
+    assert_eq!(test.status("alpha").restart_counts.schedule, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn load_servers_keeps_running_servers() {
+    let mut test = TestCluster::new("load_servers_keeps_running_servers", TWO_SERVERS);
+    test.poll().await;
+    let alpha = test.container("alpha");
+    let bravo = test.container("bravo");
+
+    test.config = toml::from_str(
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+
+        [servers.alpha]
+        name = "Alpha (renamed)"
+
+        [servers.charlie]
+        name = "Charlie"
+        "#,
+    )
+    .unwrap();
+    test.cluster.load_servers(test.servers());
+
+    // The renamed server keeps its container, and the removed one is kept until it's stopped
+    let alpha_status = test.status("alpha");
+    assert_eq!(alpha_status.name, "Alpha (renamed)");
+    assert_eq!(alpha_status.container_id, Some(alpha.id.clone()));
+    assert!(!alpha_status.is_old);
+    assert!(test.status("bravo").is_old);
+    assert_eq!(test.status("bravo").container_id, Some(bravo.id.clone()));
+
+    // Bravo's port is still in use, so Charlie gets the next one
+    test.poll().await;
+    assert_eq!(
+        label(&test.container("charlie"), GAME_PORT_LABEL),
+        Some("37017")
+    );
+
+    test.cluster.stop_old();
+    test.poll().await;
+    assert!(test.cluster.get("bravo").is_none());
+    assert!(test
+        .runtime
+        .running_container("r2wraith-default-bravo")
+        .is_none());
+    assert_eq!(test.container("alpha").id, alpha.id);
+    assert_eq!(test.cluster.status().len(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn deserialize_restores_and_rediscovers_servers() {
+    let mut test = TestCluster::new("deserialize_restores_and_rediscovers_servers", TWO_SERVERS);
+    test.poll().await;
+    let alpha = test.container("alpha");
+    let bravo = test.container("bravo");
+    let serialized_servers = test.cluster.serialize();
+    assert_eq!(serialized_servers.len(), 2);
+
+    // Bravo is left out of the restore file, so has to be found through its labels
+    test.restart_wraith();
+    let serialized_servers = serialized_servers
+        .into_iter()
+        .filter(|serialized_server| serialized_server.name == "alpha")
+        .collect();
+    test.cluster
+        .deserialize(serialized_servers, "default")
+        .await;
+
+    assert_eq!(test.status("alpha").container_id, Some(alpha.id.clone()));
+    assert_eq!(test.status("alpha").game_port, Some(37015));
+    assert_eq!(test.status("alpha").start_time, Some(alpha.created));
+    assert_eq!(test.status("bravo").container_id, Some(bravo.id.clone()));
+    assert_eq!(test.status("bravo").game_port, Some(37016));
+
+    // The game state wasn't followed while R2Wraith was away
+    assert_eq!(test.status("alpha").players, None);
+
+    assert!(matches!(test.poll().await, PollStatus::NoWork));
+    assert_eq!(test.runtime.containers().len(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn deserialize_skips_containers_that_have_gone() {
+    let mut test = TestCluster::new("deserialize_skips_containers_that_have_gone", TWO_SERVERS);
+    test.poll().await;
+    let alpha = test.container("alpha");
+    let serialized_servers = test.cluster.serialize();
+
+    test.restart_wraith();
+    test.runtime.crash(&alpha.id);
+    test.cluster
+        .deserialize(serialized_servers, "default")
+        .await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::NotRunning);
+    assert_eq!(test.status("bravo").state, ServerStateKind::Running);
+
+    // Containers from other instances are left alone
+    test.restart_wraith();
+    test.cluster
+        .deserialize(Vec::new(), "another-instance")
+        .await;
+    assert_eq!(test.status("bravo").state, ServerStateKind::NotRunning);
+}
+
+const TWO_HOSTS: &str = r#"
+game-ports = { start = 37015, end = 37020 }
+
+[[hosts]]
+name = "east"
+host = "tcp://east.example.com:2376"
+capacity = 1
+
+[[hosts]]
+name = "west"
+host = "tcp://west.example.com:2376"
+capacity = 2
+
+[defaults]
+docker-image = "northstar-dedicated"
+game-dir = "/titanfall"
+
+[servers.alpha]
+name = "Alpha"
+
+[servers.bravo]
+name = "Bravo"
+
+[servers.charlie]
+name = "Charlie"
+"#;
+
+#[tokio::test(start_paused = true)]
+async fn servers_are_placed_on_hosts_with_the_most_room() {
+    let mut test = TestCluster::new(
+        "servers_are_placed_on_hosts_with_the_most_room",
+        &format!("{}\n[servers.delta]\nname = \"Delta\"\n", TWO_HOSTS),
+    );
+    test.poll().await;
+
+    // West has room for two, so takes the first server, then they're tied and east comes first
+    assert_eq!(test.status("alpha").host.as_deref(), Some("west"));
+    assert_eq!(test.status("bravo").host.as_deref(), Some("east"));
+    assert_eq!(test.status("charlie").host.as_deref(), Some("west"));
+    assert_eq!(test.status("delta").state, ServerStateKind::NotRunning);
+    assert!(matches!(
+        test.cluster.get_mut("delta").unwrap().placement_error,
+        Some(PlacementError::HostsFull(_))
+    ));
+    assert_eq!(test.host_runtime("east").containers().len(), 1);
+    assert_eq!(test.host_runtime("west").containers().len(), 2);
+
+    // Ports are only shared between servers on the same host
+    assert_eq!(test.status("alpha").game_port, Some(37015));
+    assert_eq!(test.status("bravo").game_port, Some(37015));
+    assert_eq!(test.status("charlie").game_port, Some(37016));
+
+    // Space that's freed up is used by the server that was waiting
+    test.cluster.hold(&["bravo".to_string()]);
+    test.poll().await;
+    assert_eq!(test.status("delta").host.as_deref(), Some("east"));
+    assert_eq!(test.cluster.get_mut("delta").unwrap().placement_error, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn pinned_server_waits_for_its_host() {
+    let mut test = TestCluster::new(
+        "pinned_server_waits_for_its_host",
+        &TWO_HOSTS
+            .replace("name = \"Bravo\"", "name = \"Bravo\"\nhost = \"east\"")
+            .replace("name = \"Charlie\"", "name = \"Charlie\"\nhost = \"east\""),
+    );
+    test.poll().await;
+    assert_eq!(test.status("alpha").host.as_deref(), Some("west"));
+    assert_eq!(test.status("bravo").host.as_deref(), Some("east"));
+    assert_eq!(test.status("charlie").state, ServerStateKind::NotRunning);
+    assert_eq!(test.host_runtime("west").containers().len(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn servers_are_restored_on_their_hosts() {
+    let mut test = TestCluster::new("servers_are_restored_on_their_hosts", TWO_HOSTS);
+    test.poll().await;
+    let alpha = test
+        .host_runtime("west")
+        .running_container("r2wraith-default-alpha")
+        .unwrap();
+    let bravo = test
+        .host_runtime("east")
+        .running_container("r2wraith-default-bravo")
+        .unwrap();
+    let serialized_servers = test.cluster.serialize();
+    assert!(serialized_servers.iter().any(|serialized_server| {
+        serialized_server.name == "bravo" && serialized_server.host.as_deref() == Some("east")
+    }));
+
+    // Charlie is left out, so has to be found by listing each host's containers
+    test.restart_wraith();
+    let serialized_servers = serialized_servers
+        .into_iter()
+        .filter(|serialized_server| serialized_server.name != "charlie")
+        .collect();
+    test.cluster
+        .deserialize(serialized_servers, "default")
+        .await;
+    assert_eq!(test.status("alpha").container_id, Some(alpha.id.clone()));
+    assert_eq!(test.status("bravo").host.as_deref(), Some("east"));
+    assert_eq!(test.status("charlie").host.as_deref(), Some("west"));
+
+    // Each fake numbers its containers from one, so events have to be matched by host too
+    let west_twin = test
+        .host_runtime("west")
+        .containers()
+        .into_iter()
+        .find(|container| container.id == bravo.id)
+        .unwrap();
+    test.host_runtime("east").crash(&bravo.id);
+    let event = ContainerEvent {
+        host_name: "east".to_string(),
+        container_id: bravo.id.clone(),
+        kind: crate::container_watcher::ContainerEventKind::Died,
+    };
+    assert!(test.cluster.handle_container_event(&event));
+    assert_eq!(test.status("bravo").state, ServerStateKind::NotRunning);
+    let west_twin_id = label(&west_twin, SERVER_LABEL).unwrap();
+    assert_eq!(test.status(west_twin_id).state, ServerStateKind::Running);
+}
+
+#[tokio::test(start_paused = true)]
+async fn restore_file_without_hosts_uses_the_first_host() {
+    let mut test = TestCluster::new(
+        "restore_file_without_hosts_uses_the_first_host",
+        TWO_SERVERS,
+    );
+    test.poll().await;
+    let alpha = test.container("alpha");
+
+    test.restart_wraith();
+    let serialized_servers: Vec<SerializedServer> = serde_json::from_str(&format!(
+        r#"[{{"name": "alpha", "container_id": "{}", "game_port": 37015}}]"#,
+        alpha.id
+    ))
+    .unwrap();
+    test.cluster
+        .deserialize(serialized_servers, "another-instance")
+        .await;
+    assert_eq!(test.status("alpha").host.as_deref(), Some("local"));
+    assert_eq!(test.status("alpha").container_id, Some(alpha.id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_server_is_given_time_to_stop() {
+    let mut test = TestCluster::new("slow_server_is_given_time_to_stop", TWO_SERVERS);
+    test.runtime.set_stop_behaviour(
+        "r2wraith-default-alpha",
+        StopBehaviour::Slow(Duration::from_secs(3)),
+    );
+    test.poll().await;
+
+    let stop_start = Instant::now();
+    test.cluster.stop_all().await;
+    let stop_duration = stop_start.elapsed();
+    assert!(stop_duration >= Duration::from_secs(3));
+    assert!(stop_duration < Duration::from_secs(4));
+    assert!(test.runtime.containers().is_empty());
+    assert_eq!(test.status("alpha").state, ServerStateKind::NotRunning);
+}
+
+#[tokio::test(start_paused = true)]
+async fn wedged_server_is_killed_then_removed() {
+    let mut test = TestCluster::new(
+        "wedged_server_is_killed_then_removed",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+        stop-timeout-seconds = 20.0
+
+        [servers.alpha]
+        name = "Alpha"
+
+        [servers.bravo]
+        name = "Bravo"
+        "#,
+    );
+    test.runtime
+        .set_stop_behaviour("r2wraith-default-bravo", StopBehaviour::Wedged);
+    test.poll().await;
+
+    // Both servers are stopped at once, so the wedged one doesn't hold up the other
+    let stop_start = Instant::now();
+    test.cluster.stop_all().await;
+    let stop_duration = stop_start.elapsed();
+    assert!(stop_duration >= Duration::from_secs(20) + STOP_GRACE_PERIOD + KILL_TIMEOUT);
+    assert!(stop_duration < Duration::from_secs(20) + STOP_GRACE_PERIOD + KILL_TIMEOUT * 2);
+
+    assert!(test.runtime.containers().is_empty());
+    assert_eq!(test.status("alpha").state, ServerStateKind::NotRunning);
+    assert_eq!(test.status("bravo").state, ServerStateKind::NotRunning);
+}
+
+#[tokio::test(start_paused = true)]
+async fn restart_command_doesnt_wait_for_servers_to_stop() {
+    let mut test = TestCluster::new(
+        "restart_command_doesnt_wait_for_servers_to_stop",
+        TWO_SERVERS,
+    );
+    test.runtime
+        .set_stop_behaviour("r2wraith-default-alpha", StopBehaviour::Wedged);
+    test.poll().await;
+    let alpha = test.container("alpha");
+    let bravo = test.container("bravo");
+
+    test.cluster
+        .restart(&["alpha".to_string(), "bravo".to_string()]);
+    assert_eq!(test.status("alpha").state, ServerStateKind::Stopping);
+    assert_eq!(test.status("bravo").state, ServerStateKind::Stopping);
+
+    // Bravo is started again while alpha is still being waited on
+    test.poll().await;
+    assert_ne!(test.container("bravo").id, bravo.id);
+    assert_eq!(test.status("bravo").restart_counts.manual, 1);
+    assert_eq!(test.status("alpha").state, ServerStateKind::Stopping);
+    assert_eq!(test.status("alpha").restart_counts.manual, 0);
+
+    // Alpha is started again once it's been killed
+    sleep(Duration::from_secs(30)).await;
+    test.poll().await;
+    assert_ne!(test.container("alpha").id, alpha.id);
+    assert_eq!(test.status("alpha").restart_counts.manual, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn image_pull_policy_decides_when_to_pull() {
+    let mut test = TestCluster::new(
+        "image_pull_policy_decides_when_to_pull",
+        r#"
+        [defaults]
+        game-dir = "/titanfall"
+
+        [servers.alpha]
+        name = "Alpha"
+        docker-image = "alpha-image"
+
+        [servers.bravo]
+        name = "Bravo"
+        docker-image = "bravo-image"
+        image-pull-policy = "always"
+
+        [servers.charlie]
+        name = "Charlie"
+        docker-image = "charlie-image"
+        image-pull-policy = "on-restart"
+
+        [servers.delta]
+        name = "Delta"
+        docker-image = "delta-image"
+        image-pull-policy = "never"
+        "#,
+    );
+    for image in ["alpha-image", "bravo-image", "charlie-image", "delta-image"] {
+        test.runtime
+            .publish_image(image, &format!("sha256:{}", image));
+    }
+    let pull_count = |test: &TestCluster, image: &str| {
+        test.runtime
+            .pulled_images()
+            .iter()
+            .filter(|pulled_image| **pulled_image == format!("{}:latest", image))
+            .count()
+    };
+
+    // Pulls happen in the background, and the servers are started on the next poll
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Pulling);
+    test.poll().await;
+    assert_eq!(test.status("alpha").state, ServerStateKind::Running);
+    assert_eq!(pull_count(&test, "alpha-image"), 1);
+    assert_eq!(pull_count(&test, "bravo-image"), 1);
+    assert_eq!(pull_count(&test, "charlie-image"), 1);
+    assert_eq!(pull_count(&test, "delta-image"), 0);
+    assert_eq!(test.status("delta").state, ServerStateKind::NotRunning);
+
+    // Crashes only pull for "always"
+    for server_id in ["alpha", "bravo", "charlie"] {
+        let container = test.container(server_id);
+        test.runtime.crash(&container.id);
+    }
+    test.poll().await;
+    sleep(Duration::from_secs(2)).await;
+    test.poll().await;
+    assert_eq!(test.status("charlie").state, ServerStateKind::Running);
+    test.poll().await;
+    assert_eq!(test.status("bravo").state, ServerStateKind::Running);
+    assert_eq!(pull_count(&test, "alpha-image"), 1);
+    assert_eq!(pull_count(&test, "bravo-image"), 2);
+    assert_eq!(pull_count(&test, "charlie-image"), 1);
+
+    // Planned restarts pull for "on-restart" too
+    test.cluster.restart(&[
+        "alpha".to_string(),
+        "bravo".to_string(),
+        "charlie".to_string(),
+    ]);
+    test.poll().await;
+    test.poll().await;
+    assert_eq!(test.status("charlie").state, ServerStateKind::Running);
+    assert_eq!(pull_count(&test, "alpha-image"), 1);
+    assert_eq!(pull_count(&test, "bravo-image"), 3);
+    assert_eq!(pull_count(&test, "charlie-image"), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn newer_image_is_flagged_as_an_update() {
+    let mut test = TestCluster::new(
+        "newer_image_is_flagged_as_an_update",
+        r#"
+        [defaults]
+        docker-image = "northstar-dedicated"
+        game-dir = "/titanfall"
+
+        [servers.alpha]
+        name = "Alpha"
+
+        [servers.bravo]
+        name = "Bravo"
+        image-pull-policy = "on-restart"
+        "#,
+    );
+    test.poll().await;
+    assert!(!test.status("alpha").update_available);
+    assert!(!test.status("bravo").update_available);
+
+    test.runtime
+        .publish_image("northstar-dedicated", "sha256:2");
+    test.cluster.restart(&["bravo".to_string()]);
+    test.poll().await;
+    test.poll().await;
+    assert_eq!(test.container("bravo").image_id, "sha256:2");
+    assert!(test.status("alpha").update_available);
+    assert!(!test.status("bravo").update_available);
+
+    // Alpha picks up the newer image that's already been pulled
+    test.cluster.restart(&["alpha".to_string()]);
+    test.poll().await;
+    assert_eq!(test.container("alpha").image_id, "sha256:2");
+    assert!(!test.status("alpha").update_available);
+}
+
+const THREE_SERVERS: &str = r#"
+[defaults]
+docker-image = "northstar-dedicated"
+game-dir = "/titanfall"
+ready-log-pattern = "Test server ready"
+
+[servers.alpha]
+name = "Alpha"
+
+[servers.bravo]
+name = "Bravo"
+
+[servers.charlie]
+name = "Charlie"
+"#;
+
+#[tokio::test(start_paused = true)]
+async fn rollout_waits_for_servers_to_be_ready() {
+    let mut test = TestCluster::new("rollout_waits_for_servers_to_be_ready", THREE_SERVERS);
+    test.poll().await;
+    let first_containers: HashMap<_, _> = ["alpha", "bravo", "charlie"]
+        .into_iter()
+        .map(|server_id| (server_id, test.container(server_id).id))
+        .collect();
+    let is_restarted =
+        |test: &TestCluster, server_id| test.container(server_id).id != first_containers[server_id];
+
+    test.cluster
+        .start_rollout(Vec::new(), 2, chrono::Duration::seconds(300))
+        .unwrap();
+    assert!(test
+        .cluster
+        .start_rollout(Vec::new(), 2, chrono::Duration::seconds(300))
+        .is_err());
+    test.poll().await;
+    test.poll().await;
+    assert!(is_restarted(&test, "alpha"));
+    assert!(is_restarted(&test, "bravo"));
+    assert!(!is_restarted(&test, "charlie"));
+
+    // Charlie only goes down once one of the others is back up
+    test.poll().await;
+    assert!(!is_restarted(&test, "charlie"));
+    log_ready(&test, "alpha");
+    test.poll().await;
+    test.poll().await;
+    assert!(is_restarted(&test, "charlie"));
+
+    log_ready(&test, "bravo");
+    log_ready(&test, "charlie");
+    test.poll().await;
+    assert!(test
+        .cluster
+        .start_rollout(vec!["alpha".to_string()], 1, chrono::Duration::seconds(300))
+        .is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn rollout_is_aborted_when_a_server_fails() {
+    let mut test = TestCluster::new("rollout_is_aborted_when_a_server_fails", THREE_SERVERS);
+    test.poll().await;
+    let bravo_container = test.container("bravo");
+
+    test.cluster
+        .start_rollout(Vec::new(), 1, chrono::Duration::seconds(300))
+        .unwrap();
+    test.poll().await;
+    test.poll().await;
+    let alpha_container = test.container("alpha");
+    test.runtime.crash(&alpha_container.id);
+    test.poll().await;
+
+    // Alpha comes back from its crash, but the rest of the rollout has been abandoned
+    sleep(Duration::from_secs(2)).await;
+    test.poll().await;
+    log_ready(&test, "alpha");
+    test.poll().await;
+    assert_eq!(test.container("bravo").id, bravo_container.id);
+
+    // Servers that never get ready time out
+    test.cluster
+        .start_rollout(
+            vec!["bravo".to_string(), "charlie".to_string()],
+            1,
+            chrono::Duration::seconds(60),
+        )
+        .unwrap();
+    test.poll().await;
+    let charlie_container = test.container("charlie");
+    sleep(Duration::from_secs(61)).await;
+    test.poll().await;
+    test.poll().await;
+    assert_eq!(test.container("charlie").id, charlie_container.id);
+    assert!(test
+        .cluster
+        .start_rollout(Vec::new(), 1, chrono::Duration::seconds(60))
+        .is_ok());
+}
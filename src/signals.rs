@@ -0,0 +1,98 @@
+use crate::config::TerminateAction;
+use crate::ReplCommand;
+use log::{info, warn};
+use std::error::Error;
+use tokio::sync::mpsc::UnboundedSender;
+
+fn terminate_command(terminate_action: TerminateAction) -> ReplCommand {
+    match terminate_action {
+        TerminateAction::StopWraith => ReplCommand::StopWraith,
+        TerminateAction::StopAll => ReplCommand::StopAll,
+    }
+}
+
+/// Maps SIGTERM and SIGINT onto the configured terminate action, and SIGHUP onto a config reload.
+/// A second SIGINT exits immediately, in case stopping gets stuck.
+#[cfg(unix)]
+pub fn spawn_signal_handler(
+    terminate_action: TerminateAction,
+    repl_sender: UnboundedSender<ReplCommand>,
+    reload: impl Fn() -> Result<ReplCommand, Box<dyn Error>> + Send + 'static,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut terminate, mut interrupt, mut hangup) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::hangup()),
+    ) {
+        (Ok(terminate), Ok(interrupt), Ok(hangup)) => (terminate, interrupt, hangup),
+        _ => {
+            warn!("Failed to register signal handlers");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut is_stopping = false;
+        loop {
+            let command = tokio::select! {
+                _ = terminate.recv() => {
+                    info!("Received SIGTERM");
+                    is_stopping = true;
+                    terminate_command(terminate_action)
+                }
+                _ = interrupt.recv() => {
+                    if is_stopping {
+                        warn!("Received second SIGINT, exiting immediately");
+                        std::process::exit(130);
+                    }
+                    info!("Received SIGINT, send it again to exit immediately");
+                    is_stopping = true;
+                    terminate_command(terminate_action)
+                }
+                _ = hangup.recv() => {
+                    info!("Received SIGHUP, reloading config");
+                    match reload() {
+                        Ok(command) => command,
+                        Err(why) => {
+                            warn!("Failed to read config file: {}", why);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            if repl_sender.send(command).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Maps Ctrl-C onto the configured terminate action. A second Ctrl-C exits immediately.
+#[cfg(not(unix))]
+pub fn spawn_signal_handler(
+    terminate_action: TerminateAction,
+    repl_sender: UnboundedSender<ReplCommand>,
+    _reload: impl Fn() -> Result<ReplCommand, Box<dyn Error>> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        let mut is_stopping = false;
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if is_stopping {
+                warn!("Received second Ctrl-C, exiting immediately");
+                std::process::exit(130);
+            }
+            info!("Received Ctrl-C, press it again to exit immediately");
+            is_stopping = true;
+
+            if repl_sender
+                .send(terminate_command(terminate_action))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+}